@@ -0,0 +1,6 @@
+//! The auto-wah, built on its own since a VST library holds a single plugin.
+
+use dws::VstAutoWah;
+use vst::plugin_main;
+
+plugin_main!(VstAutoWah);
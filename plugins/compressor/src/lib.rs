@@ -0,0 +1,6 @@
+//! The compressor, built on its own since a VST library holds a single plugin.
+
+use dws::VstCompressor;
+use vst::plugin_main;
+
+plugin_main!(VstCompressor);
@@ -0,0 +1,6 @@
+//! The flanger and chorus, built on its own since a VST library holds a single plugin.
+
+use dws::Dws;
+use vst::plugin_main;
+
+plugin_main!(Dws);
@@ -0,0 +1,6 @@
+//! The plucked string synth, built on its own since a VST library holds a single plugin.
+
+use dws::VstPluckedString;
+use vst::plugin_main;
+
+plugin_main!(VstPluckedString);
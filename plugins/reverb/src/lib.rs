@@ -0,0 +1,6 @@
+//! The reverb, built on its own since a VST library holds a single plugin.
+
+use dws::VstReverb;
+use vst::plugin_main;
+
+plugin_main!(VstReverb);
@@ -0,0 +1,6 @@
+//! The ring modulator, built on its own since a VST library holds a single plugin.
+
+use dws::VstRingMod;
+use vst::plugin_main;
+
+plugin_main!(VstRingMod);
@@ -0,0 +1,6 @@
+//! The rotary speaker, built on its own since a VST library holds a single plugin.
+
+use dws::VstRotary;
+use vst::plugin_main;
+
+plugin_main!(VstRotary);
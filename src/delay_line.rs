@@ -32,29 +32,27 @@ where
         let out = self.data.slice_mut()[self.out_point];
         self.out_point = (self.out_point + 1) % self.data.slice().len();
 
-        return out;
+        out
     }
 
     /// Borrows the item at the given index relative to the input (0 is the last input value)
     pub fn tap(&self, index: usize) -> S::Element {
         assert!(index + 1 < self.data.slice().len());
 
-        let wrapped_index: usize;
-
-        if index + 1 > self.in_point {
-            wrapped_index = self.data.slice().len() - (index + 1 - self.in_point);
+        let wrapped_index = if index + 1 > self.in_point {
+            self.data.slice().len() - (index + 1 - self.in_point)
         } else {
-            wrapped_index = self.in_point - (index + 1);
-        }
+            self.in_point - (index + 1)
+        };
 
         self.data.slice()[wrapped_index]
     }
 
     pub fn get_delay(&self) -> usize {
         if self.in_point >= self.out_point {
-            return self.in_point - self.out_point;
+            self.in_point - self.out_point
         } else {
-            return self.data.slice().len() - (self.out_point - self.in_point);
+            self.data.slice().len() - (self.out_point - self.in_point)
         }
     }
 
@@ -62,13 +60,11 @@ where
     pub fn tap_output(&self, index: usize) -> S::Element {
         assert!(index + 1 < self.data.slice().len());
 
-        let wrapped_index: usize;
-
-        if index + 1 > self.out_point {
-            wrapped_index = self.data.slice().len() - (index + 1 - self.out_point);
+        let wrapped_index = if index + 1 > self.out_point {
+            self.data.slice().len() - (index + 1 - self.out_point)
         } else {
-            wrapped_index = self.out_point - (index + 1);
-        }
+            self.out_point - (index + 1)
+        };
 
         self.data.slice()[wrapped_index]
     }
@@ -85,16 +81,66 @@ where
 
     pub fn new(data: S, delay: usize) -> Self {
         assert!(data.slice().len() > 1);
-        assert!(delay <= data.slice().len() - 1);
+        assert!(delay < data.slice().len());
 
         DelayLine {
             in_point: 0,
             out_point: (data.slice().len() - delay) % data.slice().len(),
-            data: data,
+            data,
         }
     }
 }
 
+impl<S> DelayLine<S>
+where
+    S: Slice,
+    S::Element: Frame,
+{
+    /// Silences the line, keeping its delay.
+    pub fn clear(&mut self)
+    where
+        S: SliceMut,
+    {
+        for item in self.data.slice_mut() {
+            *item = S::Element::EQUILIBRIUM;
+        }
+    }
+
+    /// Reads at a fractional delay relative to the input (0 is the last input value), using
+    /// 4-point Catmull-Rom interpolation. Several taps can be read from one line this way.
+    ///
+    /// The interpolation needs a value on both sides of the delay, so it is limited to between 1
+    /// and `capacity() - 3`.
+    pub fn tap_cubic(&self, delay: f64) -> S::Element {
+        let delay = delay.max(1.0).min((self.capacity() - 3) as f64);
+        let index = delay.trunc() as usize;
+        let t = delay.fract();
+
+        let w_newer = ((-0.5 * t + 1.0) * t - 0.5) * t;
+        let w_0 = (1.5 * t - 2.5) * t * t + 1.0;
+        let w_1 = ((-1.5 * t + 2.0) * t + 0.5) * t;
+        let w_older = (0.5 * t - 0.5) * t * t;
+
+        self.tap(index)
+            .scale_amp(w_0.to_sample())
+            .add_amp(
+                self.tap(index - 1)
+                    .scale_amp(w_newer.to_sample())
+                    .to_signed_frame(),
+            )
+            .add_amp(
+                self.tap(index + 1)
+                    .scale_amp(w_1.to_sample())
+                    .to_signed_frame(),
+            )
+            .add_amp(
+                self.tap(index + 2)
+                    .scale_amp(w_older.to_sample())
+                    .to_signed_frame(),
+            )
+    }
+}
+
 pub struct DelayLineFracLin<T>
 where
     T: Slice,
@@ -109,7 +155,7 @@ where
     T::Element: Frame,
 {
     pub fn new(data: T, delay: f64) -> Self {
-        assert!(data.slice().len() > 0);
+        assert!(!data.slice().is_empty());
 
         let integer_part = delay.trunc() as usize;
         let fractional_part = delay.fract();
@@ -145,6 +191,14 @@ where
         self.delay_line.tap_output(0)
     }
 
+    /// Silences the line, keeping its delay.
+    pub fn clear(&mut self)
+    where
+        T: SliceMut,
+    {
+        self.delay_line.clear();
+    }
+
     pub fn set_delay(&mut self, delay: f64) {
         let integer_part = delay.trunc() as usize;
         let fractional_part = delay.fract();
@@ -343,4 +397,53 @@ mod tests {
         d.set_delay(99.0);
         d.tick(0);
     }
+
+    #[test]
+    pub fn cubic_taps() {
+        let mut d = DelayLine::new(vec![0.0; 100], 0);
+        let udist = Uniform::new(1.0, 96.0);
+        let mut rng = rand::thread_rng();
+
+        for n in 0..1000 {
+            d.tick(n as f64);
+
+            if n > 100 {
+                //the interpolation is exact for a ramp
+                let delay = udist.sample(&mut rng);
+                assert_relative_eq!(d.tap_cubic(delay), n as f64 - delay, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    pub fn cubic_taps_at_the_limits() {
+        let mut d = DelayLine::new(vec![0.0; 10], 0);
+        for n in 0..20 {
+            d.tick(n as f64);
+        }
+
+        assert_relative_eq!(d.tap_cubic(6.0), 13.0, epsilon = 1e-9);
+        assert_relative_eq!(d.tap_cubic(5.5), 13.5, epsilon = 1e-9);
+        // Anything longer is clamped to the longest delay that can be interpolated.
+        assert_relative_eq!(d.tap_cubic(7.0), 13.0, epsilon = 1e-9);
+        assert_relative_eq!(d.tap_cubic(100.0), 13.0, epsilon = 1e-9);
+        assert_relative_eq!(d.tap_cubic(0.0), 18.0, epsilon = 1e-9);
+    }
+
+    #[test]
+    pub fn cubic_taps_sine() {
+        //a sine at a tenth of the sample rate is reproduced much better than with linear interpolation
+        let w = 2.0 * std::f64::consts::PI / 10.0;
+        let mut d = DelayLine::new(vec![0.0; 100], 0);
+
+        for n in 0..200 {
+            d.tick((w * n as f64).sin());
+        }
+
+        for i in 10..90 {
+            let delay = i as f64 + 0.37;
+            let expected = (w * (199.0 - delay)).sin();
+            assert_relative_eq!(d.tap_cubic(delay), expected, epsilon = 1e-2);
+        }
+    }
 }
@@ -1,7 +1,41 @@
+use dasp::frame::{Mono, Stereo};
 use dasp::Frame;
 use dasp::Sample;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 
 use crate::delay_line;
+use crate::filter::{Biquad, BiquadCoefficients};
+use crate::lfo::{Lfo, Waveform};
+use crate::room;
+
+mod convolution;
+mod distortion;
+pub mod dynamics;
+mod granular;
+mod resonator;
+mod reverb;
+mod rotary;
+mod tape_delay;
+
+pub use convolution::{ConvolutionReverb, Convolver, CONVOLUTION_MAX_PRE_DELAY};
+pub use distortion::{Distortion, DistortionCurve};
+pub use granular::{
+    Granular, GRANULAR_MAX_GRAINS, GRANULAR_MAX_GRAIN_LENGTH, GRANULAR_MIN_GRAIN_LENGTH,
+};
+pub use resonator::{StringResonator, RESONATOR_MAX_STRINGS, RESONATOR_MIN_FREQUENCY};
+pub use reverb::{Fdn, FeedbackMatrix, Reverb, FDN_MAX_LINES, FDN_MIN_LINES};
+pub use rotary::{Rotary, ROTARY_CROSSOVER};
+pub use tape_delay::{TapeDelay, TAPE_DELAY_MAX_DELAY, TAPE_DELAY_MAX_FEEDBACK};
+
+fn sample_to_f64<S: Sample>(sample: S) -> f64 {
+    sample.to_float_sample().to_sample()
+}
+
+fn sample_from_f64<S: Sample>(value: f64) -> S {
+    value.to_sample::<S::Float>().to_sample()
+}
 
 #[derive(Clone, Copy)]
 pub struct EchoParameters {
@@ -12,7 +46,7 @@ pub struct EchoParameters {
 impl EchoParameters {
     pub fn from_distances(d: f32, h: f32, sample_rate: usize) -> Self {
         let frame_t = 1.0 / sample_rate as f32;
-        let r = ((h * h + (d * d / 4.0)) as f32).sqrt();
+        let r = (h * h + (d * d / 4.0)).sqrt();
         let m = ((2.0 * r - d) / (343.0 * frame_t)).round() as usize;
         let g = d / (2.0 * r);
 
@@ -23,50 +57,341 @@ impl EchoParameters {
     }
 }
 
+/// Duration in seconds of a note value at a tempo. The note value is a fraction of a whole note,
+/// so a quarter is 0.25, a dotted eighth 0.1875 and an eighth triplet 1/12.
+pub fn note_duration(note: f64, bpm: f64) -> f64 {
+    note * 4.0 * 60.0 / bpm
+}
+
+/// Feedback of the echo is clamped to this magnitude.
+pub const ECHO_MAX_FEEDBACK: f64 = 0.99;
+
 pub struct Echo<T> {
     delay_line: delay_line::DelayLine<Vec<T>>,
     params: EchoParameters,
+    sample_rate: usize,
+    feedback: f64,
+    lowpass: Option<Biquad<T>>,
+    highpass: Option<Biquad<T>>,
+    saturation: bool,
+    ping_pong: bool,
 }
 
 impl<T: Frame> Echo<T> {
     pub fn new(d: f32, h: f32, sample_rate: usize, capacity: usize) -> Self {
-        let params = EchoParameters::from_distances(d, h, sample_rate);
+        Echo::with_params(
+            EchoParameters::from_distances(d, h, sample_rate),
+            sample_rate,
+            capacity,
+        )
+    }
 
+    fn with_params(params: EchoParameters, sample_rate: usize, capacity: usize) -> Self {
         assert!(params.length < capacity);
 
         Echo {
-            delay_line: delay_line::DelayLine::new(vec![T::EQUILIBRIUM; capacity], params.length),
-            params: params,
+            delay_line: delay_line::DelayLine::new(vec![T::EQUILIBRIUM; capacity], 0),
+            params,
+            sample_rate,
+            feedback: 0.0,
+            lowpass: None,
+            highpass: None,
+            saturation: false,
+            ping_pong: false,
         }
     }
 
-    pub fn set_params(self: &mut Self, params: EchoParameters) {
+    /// An echo with a delay given in milliseconds and a wet gain, for use as a musical delay.
+    pub fn with_delay_ms(
+        delay: f64,
+        attenuation: f32,
+        sample_rate: usize,
+        capacity: usize,
+    ) -> Self {
+        let params = EchoParameters {
+            attenuation,
+            length: 0,
+        };
+        let mut echo = Echo::with_params(params, sample_rate, capacity);
+        echo.set_delay_ms(delay);
+        echo
+    }
+
+    pub fn set_params(&mut self, params: EchoParameters) {
+        assert!(params.length <= self.delay_line.capacity());
+
         self.params = params;
+    }
+
+    pub fn set_delay_ms(&mut self, delay: f64) {
+        let length = (delay / 1000.0 * self.sample_rate as f64).round() as usize;
+        self.params.length = length.min(self.delay_line.capacity());
+    }
 
-        self.delay_line.set_delay(params.length);
+    /// Sets the delay to a note value (see `note_duration`) at the given tempo.
+    pub fn set_delay_note(&mut self, note: f64, bpm: f64) {
+        self.set_delay_ms(note_duration(note, bpm) * 1000.0);
     }
 
-    pub fn tick(self: &mut Self, in_frame: T) -> T {
+    /// Gain of the echoed signal mixed into the output.
+    pub fn set_attenuation(&mut self, attenuation: f32) {
+        self.params.attenuation = attenuation;
+    }
+
+    /// Amount of the echo fed back into the delay, clamped to `ECHO_MAX_FEEDBACK`.
+    pub fn set_feedback(&mut self, feedback: f64) {
+        self.feedback = feedback.clamp(-ECHO_MAX_FEEDBACK, ECHO_MAX_FEEDBACK);
+    }
+
+    /// Lowpass in the feedback loop, so every repeat gets darker. `None` turns it off.
+    pub fn set_lowpass(&mut self, frequency: Option<f64>) {
+        self.lowpass = frequency.map(|f| {
+            Biquad::new(BiquadCoefficients::lowpass(
+                f,
+                std::f64::consts::FRAC_1_SQRT_2,
+                self.sample_rate,
+            ))
+        });
+    }
+
+    /// Highpass in the feedback loop, so every repeat gets thinner. `None` turns it off.
+    pub fn set_highpass(&mut self, frequency: Option<f64>) {
+        self.highpass = frequency.map(|f| {
+            Biquad::new(BiquadCoefficients::highpass(
+                f,
+                std::f64::consts::FRAC_1_SQRT_2,
+                self.sample_rate,
+            ))
+        });
+    }
+
+    /// Soft clips the feedback signal with tanh.
+    pub fn set_saturation(&mut self, saturation: bool) {
+        self.saturation = saturation;
+    }
+
+    /// In ping-pong mode the input is mixed down into the first channel, and every repeat moves
+    /// on to the next channel, so for stereo the repeats alternate between left and right.
+    pub fn set_ping_pong(&mut self, ping_pong: bool) {
+        self.ping_pong = ping_pong;
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
         let signed_in = in_frame.to_signed_frame();
 
-        let out = self.delay_line.tick(in_frame);
+        // Read before writing, so the feedback loop is exactly as long as the delay.
+        let out = if self.params.length == 0 {
+            in_frame
+        } else {
+            self.delay_line.tap(self.params.length - 1)
+        };
+
+        let mut recirculated = out;
+        if let Some(lowpass) = self.lowpass.as_mut() {
+            recirculated = lowpass.tick(recirculated);
+        }
+        if let Some(highpass) = self.highpass.as_mut() {
+            recirculated = highpass.tick(recirculated);
+        }
+        if self.saturation {
+            recirculated = recirculated.map(|s| sample_from_f64(sample_to_f64(s).tanh()));
+        }
+
+        let line_in = if self.ping_pong {
+            recirculated = T::from_fn(|c| {
+                *recirculated
+                    .channel((c + T::CHANNELS - 1) % T::CHANNELS)
+                    .unwrap()
+            });
+
+            let mono = in_frame.channels().map(sample_to_f64).sum::<f64>() / T::CHANNELS as f64;
+            T::from_fn(|c| {
+                if c == 0 {
+                    sample_from_f64(mono)
+                } else {
+                    T::Sample::EQUILIBRIUM
+                }
+            })
+        } else {
+            in_frame
+        };
+
+        self.delay_line.tick(
+            line_in.add_amp(
+                recirculated
+                    .scale_amp(self.feedback.to_sample())
+                    .to_signed_frame(),
+            ),
+        );
 
         out.scale_amp(self.params.attenuation.to_sample())
             .add_amp(signed_in)
     }
 }
 
+/// Sums several delayed and attenuated copies of the input, for example the reflections
+/// computed by `room::Room::reflections`.
+pub struct MultiTapDelay<T> {
+    delay_line: delay_line::DelayLine<Vec<T>>,
+    taps: Vec<EchoParameters>,
+}
+
+impl<T: Frame> MultiTapDelay<T> {
+    pub fn new(taps: Vec<EchoParameters>) -> Self {
+        let longest = taps.iter().map(|t| t.length).max().unwrap_or(0);
+
+        MultiTapDelay {
+            delay_line: delay_line::DelayLine::new(vec![T::EQUILIBRIUM; longest + 2], 0),
+            taps,
+        }
+    }
+
+    /// Replaces the taps. None of them may be longer than the longest one given to `new`.
+    pub fn set_taps(&mut self, taps: Vec<EchoParameters>) {
+        assert!(taps.iter().all(|t| t.length < self.delay_line.capacity()));
+
+        self.taps = taps;
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        self.delay_line.tick(in_frame);
+
+        let mut out = T::EQUILIBRIUM;
+        for tap in self.taps.iter() {
+            out = out.add_amp(
+                self.delay_line
+                    .tap(tap.length)
+                    .scale_amp(tap.attenuation.to_sample())
+                    .to_signed_frame(),
+            );
+        }
+        out
+    }
+}
+
+/// A source moving along a trajectory past a fixed listener above a reflecting ground at z = 0.
+///
+/// The delays of the direct path and the ground reflection follow the geometry sample by sample
+/// on a fractional delay line, which gives the Doppler shift, and each path is attenuated by
+/// distance relative to 1 m.
+pub struct MovingSource<T> {
+    delay_line: delay_line::DelayLine<Vec<T>>,
+    // Positions at given times in seconds, moved between linearly.
+    trajectory: Vec<(f64, [f64; 3])>,
+    listener: [f64; 3],
+    ground_reflection: f64,
+    frame: usize,
+    sample_rate: f64,
+}
+
+impl<T: Frame> MovingSource<T> {
+    pub fn new(trajectory: Vec<(f64, [f64; 3])>, listener: [f64; 3], sample_rate: usize) -> Self {
+        assert!(!trajectory.is_empty());
+        assert!(trajectory.windows(2).all(|w| w[0].0 < w[1].0));
+
+        // The distance to a straight segment is largest at one of its ends.
+        let longest = trajectory
+            .iter()
+            .map(|(_, p)| room::distance(ground_image(*p), listener))
+            .fold(0.0, f64::max);
+        let capacity = (longest / room::SPEED_OF_SOUND * sample_rate as f64).ceil() as usize + 4;
+
+        MovingSource {
+            delay_line: delay_line::DelayLine::new(vec![T::EQUILIBRIUM; capacity], 0),
+            trajectory,
+            listener,
+            ground_reflection: 1.0,
+            frame: 0,
+            sample_rate: sample_rate as f64,
+        }
+    }
+
+    /// Pressure gain of the ground reflection, 0 turns it off.
+    pub fn set_ground_reflection(&mut self, gain: f64) {
+        self.ground_reflection = gain;
+    }
+
+    /// Where the source is at the given time. It stays put before the first and after the last
+    /// point of the trajectory.
+    pub fn position(&self, time: f64) -> [f64; 3] {
+        let next = self.trajectory.iter().position(|(t, _)| *t > time);
+        match next {
+            None => self.trajectory[self.trajectory.len() - 1].1,
+            Some(0) => self.trajectory[0].1,
+            Some(i) => {
+                let (t0, p0) = self.trajectory[i - 1];
+                let (t1, p1) = self.trajectory[i];
+                let a = (time - t0) / (t1 - t0);
+                [
+                    p0[0] + a * (p1[0] - p0[0]),
+                    p0[1] + a * (p1[1] - p0[1]),
+                    p0[2] + a * (p1[2] - p0[2]),
+                ]
+            }
+        }
+    }
+
+    /// Delay in samples and gain of the path from where the source was when it emitted the
+    /// sound arriving now, found by fixed point iteration.
+    fn path(&self, time: f64, mirror: fn([f64; 3]) -> [f64; 3]) -> (f64, f64) {
+        let mut travel = 0.0;
+        for _ in 0..4 {
+            let source = mirror(self.position(time - travel));
+            travel = room::distance(source, self.listener) / room::SPEED_OF_SOUND;
+        }
+
+        let distance = travel * room::SPEED_OF_SOUND;
+        (travel * self.sample_rate, 1.0 / distance.max(1.0))
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        self.delay_line.tick(in_frame);
+
+        let time = self.frame as f64 / self.sample_rate;
+        self.frame += 1;
+
+        let (direct_delay, direct_gain) = self.path(time, |p| p);
+        let mut out = self
+            .delay_line
+            .tap_cubic(direct_delay)
+            .scale_amp(direct_gain.to_sample());
+
+        if self.ground_reflection != 0.0 {
+            let (ground_delay, ground_gain) = self.path(time, ground_image);
+            out = out.add_amp(
+                self.delay_line
+                    .tap_cubic(ground_delay)
+                    .scale_amp((ground_gain * self.ground_reflection).to_sample())
+                    .to_signed_frame(),
+            );
+        }
+
+        out
+    }
+}
+
+fn ground_image(position: [f64; 3]) -> [f64; 3] {
+    [position[0], position[1], -position[2]]
+}
+
+/// Feedback is clamped to this magnitude to keep the flanger stable.
+pub const FLANGE_MAX_FEEDBACK: f64 = 0.95;
+
 pub struct FlangeParameters {
     frame_time: f64,
-    rate: f64,
     amount: f64,
     depth: f64,
+    feedback: f64,
+    through_zero: bool,
 }
 
 pub struct Flange<T> {
     params: FlangeParameters,
     delay_line: delay_line::DelayLineFracLin<Vec<T>>,
-    time: f64,
+    // Only used in through-zero mode, where the dry path is delayed to the centre of the sweep.
+    dry_delay_line: delay_line::DelayLineFracLin<Vec<T>>,
+    last_wet: T,
+    lfo: Lfo,
 }
 
 impl<T: Frame> Flange<T> {
@@ -74,37 +399,1890 @@ impl<T: Frame> Flange<T> {
         Flange {
             params: FlangeParameters {
                 frame_time: 1.0 / sample_rate as f64,
-                rate: rate,
                 amount: amount * sample_rate as f64,
-                depth: depth,
+                depth,
+                feedback: 0.0,
+                through_zero: false,
             },
             delay_line: delay_line::DelayLineFracLin::new(vec![T::EQUILIBRIUM; 10000], 1000.0),
-            time: 0.0,
+            dry_delay_line: delay_line::DelayLineFracLin::new(vec![T::EQUILIBRIUM; 10000], 0.0),
+            last_wet: T::EQUILIBRIUM,
+            lfo: Lfo::new(Waveform::Sine, rate, sample_rate),
         }
     }
 
-    pub fn tick(self: &mut Self, in_frame: T) -> T {
-        self.time += self.params.frame_time;
-        let sine = (self.params.rate * self.time * 2.0 * std::f64::consts::PI).sin(); //Would a look up table give better performance?
+    pub fn tick(&mut self, in_frame: T) -> T {
+        self.tick_with_feedback_from(in_frame, self.last_wet)
+    }
+
+    /// Like `tick`, but regenerates from `feedback_source` instead of this flanger's own output.
+    fn tick_with_feedback_from(&mut self, in_frame: T, feedback_source: T) -> T {
+        let modulation = self.lfo.tick();
+        let centre = self.params.amount + 0.0005;
         self.delay_line
-            .set_delay(self.params.amount * sine + self.params.amount + 0.0005);
-        let a = self.delay_line.tick(in_frame);
-        let out = a
-            .scale_amp(self.params.depth.to_sample())
-            .add_amp(in_frame.to_signed_frame());
+            .set_delay(self.params.amount * modulation + centre);
+
+        let regenerated = in_frame.add_amp(
+            feedback_source
+                .scale_amp(self.params.feedback.to_sample())
+                .to_signed_frame(),
+        );
+        let wet = self.delay_line.tick(regenerated);
+        self.last_wet = wet;
 
-        return out;
+        let dry = if self.params.through_zero {
+            self.dry_delay_line.set_delay(centre);
+            self.dry_delay_line.tick(in_frame)
+        } else {
+            in_frame
+        };
+
+        wet.scale_amp(self.params.depth.to_sample())
+            .add_amp(dry.to_signed_frame())
     }
 
     pub fn set_rate(&mut self, rate: f64) {
-        self.params.rate = rate;
+        self.lfo.set_rate(rate);
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.lfo.set_waveform(waveform);
+    }
+
+    /// Phase offset of the modulation in cycles.
+    pub fn set_phase_offset(&mut self, offset: f64) {
+        self.lfo.set_phase_offset(offset);
     }
 
     pub fn set_amount(&mut self, amount: f64) {
         self.params.amount = amount / self.params.frame_time;
     }
 
+    /// Gain of the delayed signal. Negative values invert it, which with through-zero
+    /// gives complete cancellation where the two paths cross.
     pub fn set_depth(&mut self, depth: f64) {
         self.params.depth = depth;
     }
+
+    /// Amount of the delayed signal fed back into the delay line. Negative values give the
+    /// hollow sounding odd harmonic resonances. Clamped to `FLANGE_MAX_FEEDBACK`.
+    pub fn set_feedback(&mut self, feedback: f64) {
+        self.params.feedback = feedback.clamp(-FLANGE_MAX_FEEDBACK, FLANGE_MAX_FEEDBACK);
+    }
+
+    /// In through-zero mode the dry signal is delayed by the centre of the sweep, so the
+    /// modulated path passes from behind it to ahead of it.
+    pub fn set_through_zero(&mut self, through_zero: bool) {
+        self.params.through_zero = through_zero;
+    }
+}
+
+/// Flanger with separately modulated channels.
+///
+/// The right channel's modulation lags the left by the spread, and the cross feedback moves the
+/// regeneration of each channel from its own delayed signal over to the other channel's.
+pub struct StereoFlange<S> {
+    left: Flange<Mono<S>>,
+    right: Flange<Mono<S>>,
+    cross_feedback: f64,
+}
+
+impl<S: Sample> StereoFlange<S> {
+    pub fn new(rate: f64, amount: f64, depth: f64, sample_rate: usize) -> Self {
+        StereoFlange {
+            left: Flange::new(rate, amount, depth, sample_rate),
+            right: Flange::new(rate, amount, depth, sample_rate),
+            cross_feedback: 0.0,
+        }
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let left_last = self.left.last_wet;
+        let right_last = self.right.last_wet;
+        let cross = self.cross_feedback.to_sample();
+
+        let left_source = left_last
+            .scale_amp((1.0 - self.cross_feedback).to_sample())
+            .add_amp(right_last.scale_amp(cross).to_signed_frame());
+        let right_source = right_last
+            .scale_amp((1.0 - self.cross_feedback).to_sample())
+            .add_amp(left_last.scale_amp(cross).to_signed_frame());
+
+        let left = self
+            .left
+            .tick_with_feedback_from([in_frame[0]], left_source);
+        let right = self
+            .right
+            .tick_with_feedback_from([in_frame[1]], right_source);
+
+        [left[0], right[0]]
+    }
+
+    /// Phase difference between the left and right modulation, in degrees from 0 to 180.
+    pub fn set_spread(&mut self, degrees: f64) {
+        self.right
+            .set_phase_offset(degrees.clamp(0.0, 180.0) / 360.0);
+    }
+
+    /// How much of each channel's feedback is taken from the other channel, from 0 to 1.
+    pub fn set_cross_feedback(&mut self, cross_feedback: f64) {
+        self.cross_feedback = cross_feedback.clamp(0.0, 1.0);
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        self.left.set_rate(rate);
+        self.right.set_rate(rate);
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.left.set_waveform(waveform);
+        self.right.set_waveform(waveform);
+    }
+
+    pub fn set_amount(&mut self, amount: f64) {
+        self.left.set_amount(amount);
+        self.right.set_amount(amount);
+    }
+
+    pub fn set_depth(&mut self, depth: f64) {
+        self.left.set_depth(depth);
+        self.right.set_depth(depth);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f64) {
+        self.left.set_feedback(feedback);
+        self.right.set_feedback(feedback);
+    }
+
+    pub fn set_through_zero(&mut self, through_zero: bool) {
+        self.left.set_through_zero(through_zero);
+        self.right.set_through_zero(through_zero);
+    }
+}
+
+/// Longest delay the vibrato's modulation can reach, in seconds. Deep and slow settings are
+/// limited by this.
+pub const VIBRATO_MAX_DELAY: f64 = 0.05;
+
+/// Pitch vibrato: only the modulated delay, without the dry signal.
+///
+/// A delay changing by `d` samples per sample plays the input back at `1 - d` times its speed,
+/// so the modulation amplitude is chosen to make the highest pitch `depth` cents above the input.
+/// The lowest pitch is slightly further below the input, about 6 cents more at a depth of 100.
+pub struct Vibrato<T> {
+    delay_line: delay_line::DelayLine<Vec<T>>,
+    lfo: Lfo,
+    depth: f64,
+    // Modulation amplitude in samples.
+    amplitude: f64,
+    sample_rate: f64,
+}
+
+impl<T: Frame> Vibrato<T> {
+    /// `depth` in cents and `rate` in Hz.
+    pub fn new(depth: f64, rate: f64, sample_rate: usize) -> Self {
+        let capacity = (VIBRATO_MAX_DELAY * sample_rate as f64).ceil() as usize + 4;
+        let mut vibrato = Vibrato {
+            delay_line: delay_line::DelayLine::new(vec![T::EQUILIBRIUM; capacity], 0),
+            lfo: Lfo::new(Waveform::Sine, rate, sample_rate),
+            depth,
+            amplitude: 0.0,
+            sample_rate: sample_rate as f64,
+        };
+        vibrato.update_amplitude();
+        vibrato
+    }
+
+    fn update_amplitude(&mut self) {
+        let rate = self.lfo.get_rate();
+        let largest = (self.delay_line.capacity() as f64 - 4.0) / 2.0;
+        self.amplitude = if rate > 0.0 {
+            let speed = 2.0 * std::f64::consts::PI * rate / self.sample_rate;
+            ((2f64.powf(self.depth.abs() / 1200.0) - 1.0) / speed).min(largest)
+        } else {
+            0.0
+        };
+    }
+
+    /// The delay in samples, centred so it never gets shorter than the cubic interpolation needs.
+    fn delay(&self, modulation: f64) -> f64 {
+        self.amplitude * (1.0 - modulation) + 1.0
+    }
+
+    /// Delay in samples between the input and the output when the pitch is unchanged.
+    pub fn latency(&self) -> f64 {
+        self.delay(0.0)
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        self.delay_line.tick(in_frame);
+        let modulation = self.lfo.tick();
+        self.delay_line.tap_cubic(self.delay(modulation))
+    }
+
+    /// Depth in cents.
+    pub fn set_depth(&mut self, depth: f64) {
+        self.depth = depth;
+        self.update_amplitude();
+    }
+
+    /// Rate in Hz. The same depth needs more delay at lower rates.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.lfo.set_rate(rate);
+        self.update_amplitude();
+    }
+}
+
+/// Longest crossfade window of the pitch shifter, in seconds.
+pub const PITCH_SHIFTER_MAX_WINDOW: f64 = 0.1;
+
+/// Furthest the pitch shifter searches for a splice point, in seconds.
+pub const PITCH_SHIFTER_MAX_SEARCH: f64 = 0.025;
+
+// Steps the coarse splice search takes across the search range on either side.
+const PITCH_SHIFTER_SEARCH_DECIMATION: usize = 32;
+
+struct PitchShifterHead {
+    // Position in the crossfade window, the gain is sin^2(pi * age).
+    age: f64,
+    // Extra delay in samples chosen when the head last restarted.
+    offset: f64,
+}
+
+/// Pitch shifter with two read heads sweeping a delay line at the shifted speed.
+///
+/// Each head starts at one end of the window, runs to the other end and restarts, fading out
+/// and in around the jump while the other head, half a window further on, is at full gain. Where
+/// a head restarts is moved by up to the search range to where the signal best matches what the
+/// other head is playing, which keeps periodic input from partially cancelling in the crossfade.
+///
+/// Only the shifted signal is output, so several can be mixed with the dry signal as harmonizer
+/// voices.
+pub struct PitchShifter<T> {
+    delay_line: delay_line::DelayLine<Vec<T>>,
+    heads: [PitchShifterHead; 2],
+    ratio: f64,
+    // In samples.
+    window: f64,
+    search: usize,
+    sample_rate: f64,
+}
+
+impl<T: Frame> PitchShifter<T> {
+    pub fn new(semitones: f64, cents: f64, sample_rate: usize) -> Self {
+        let capacity = ((PITCH_SHIFTER_MAX_WINDOW + 3.0 * PITCH_SHIFTER_MAX_SEARCH)
+            * sample_rate as f64)
+            .ceil() as usize
+            + 8;
+        let mut shifter = PitchShifter {
+            delay_line: delay_line::DelayLine::new(vec![T::EQUILIBRIUM; capacity], 0),
+            heads: [
+                PitchShifterHead {
+                    age: 0.0,
+                    offset: 0.0,
+                },
+                PitchShifterHead {
+                    age: 0.5,
+                    offset: 0.0,
+                },
+            ],
+            ratio: 1.0,
+            window: 0.0,
+            search: 0,
+            sample_rate: sample_rate as f64,
+        };
+        shifter.set_shift(semitones, cents);
+        shifter.set_window(0.05);
+        shifter.set_search(0.01);
+        shifter
+    }
+
+    /// Delay in samples of a head at the given position, with its offset.
+    fn head_delay(&self, age: f64, offset: f64) -> f64 {
+        let travelled = if self.ratio > 1.0 { 1.0 - age } else { age };
+        1.0 + offset + travelled * self.window
+    }
+
+    /// Delay in samples between the input and the output when the pitch is unchanged.
+    pub fn latency(&self) -> f64 {
+        self.head_delay(0.5, 0.0)
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        self.delay_line.tick(in_frame);
+
+        let mut out = T::EQUILIBRIUM;
+        for head in self.heads.iter() {
+            let gain = (std::f64::consts::PI * head.age).sin().powi(2);
+            let delay = self.head_delay(head.age, head.offset);
+            out = out.add_amp(
+                self.delay_line
+                    .tap_cubic(delay)
+                    .scale_amp(gain.to_sample())
+                    .to_signed_frame(),
+            );
+        }
+
+        let step = (self.ratio - 1.0).abs() / self.window;
+        for i in 0..self.heads.len() {
+            self.heads[i].age += step;
+            if self.heads[i].age >= 1.0 {
+                self.heads[i].age -= 1.0;
+                self.heads[i].offset = self.splice_offset(i);
+            }
+        }
+
+        out
+    }
+
+    /// The offset for a restarting head that best lines it up with the other head.
+    fn splice_offset(&self, restarting: usize) -> f64 {
+        if self.search == 0 {
+            return 0.0;
+        }
+
+        let other = &self.heads[1 - restarting];
+        let other_delay = self.head_delay(other.age, other.offset);
+        let start = self.head_delay(self.heads[restarting].age, 0.0);
+        let fraction = other_delay.fract() - start.fract();
+        let start = start.trunc() as usize;
+        let other_delay = other_delay.trunc() as usize;
+
+        let mono = |index: usize| -> f64 {
+            self.delay_line
+                .tap(index)
+                .channels()
+                .map(sample_to_f64)
+                .sum()
+        };
+        // How well the restarting head at `offset` matches the other head, comparing every
+        // `stride`th sample.
+        let score = |offset: usize, stride: usize| -> f64 {
+            let (correlation, energy) =
+                (0..self.search)
+                    .step_by(stride)
+                    .fold((0.0, 0.0), |(c, e), m| {
+                        let x = mono(start + offset + m);
+                        (c + x * mono(other_delay + m), e + x * x)
+                    });
+            if energy > 0.0 {
+                correlation / energy.sqrt()
+            } else {
+                0.0
+            }
+        };
+        let best = |offsets: std::ops::RangeInclusive<usize>, step: usize, stride: usize| {
+            offsets
+                .step_by(step)
+                .map(|offset| (score(offset, stride), offset))
+                .fold((f64::MIN, 0), |best, s| if s.0 > best.0 { s } else { best })
+        };
+
+        // Searching every offset sample by sample would take (2 * search + 1) * search taps in
+        // one tick. A coarse pass over a decimated grid, then a fine one around its best offset,
+        // takes about 1 / PITCH_SHIFTER_SEARCH_DECIMATION of that.
+        let stride = (self.search / PITCH_SHIFTER_SEARCH_DECIMATION).max(1);
+        let coarse = best(0..=2 * self.search, stride, stride).1;
+        let fine = best(
+            coarse.saturating_sub(stride)..=(coarse + stride).min(2 * self.search),
+            1,
+            1,
+        );
+
+        (fine.1 as f64 + fraction).max(0.0)
+    }
+
+    /// Shift up or down by the sum of both, 0 leaves the pitch unchanged.
+    pub fn set_shift(&mut self, semitones: f64, cents: f64) {
+        let ratio = 2f64.powf((semitones * 100.0 + cents) / 1200.0);
+
+        // The heads run the other way through the window now. Mirroring their positions keeps
+        // both their delays and their gains.
+        if (ratio > 1.0) != (self.ratio > 1.0) {
+            for head in self.heads.iter_mut() {
+                head.age = 1.0 - head.age;
+            }
+        }
+        self.ratio = ratio;
+    }
+
+    /// Length of the window the heads sweep, in seconds up to `PITCH_SHIFTER_MAX_WINDOW`.
+    /// Longer windows splice less often but smear transients.
+    pub fn set_window(&mut self, window: f64) {
+        self.window = (window.clamp(0.001, PITCH_SHIFTER_MAX_WINDOW) * self.sample_rate).round();
+    }
+
+    /// How far to search for splice points in seconds, up to `PITCH_SHIFTER_MAX_SEARCH`. 0 turns
+    /// the search off.
+    pub fn set_search(&mut self, search: f64) {
+        self.search = (search.clamp(0.0, PITCH_SHIFTER_MAX_SEARCH) * self.sample_rate) as usize;
+    }
+}
+
+pub const CHORUS_MAX_VOICES: usize = 8;
+
+/// Longest delay any chorus voice can reach, base delay and modulation included, in seconds.
+pub const CHORUS_MAX_DELAY: f64 = 0.1;
+
+// Default base delay (s), rate relative to the chorus rate, LFO phase (cycles) and pan per voice.
+const CHORUS_DEFAULT_VOICES: [(f64, f64, f64, f64); CHORUS_MAX_VOICES] = [
+    (0.012, 1.0, 0.0, -0.6),
+    (0.017, 1.13, 0.5, 0.6),
+    (0.022, 0.87, 0.25, -0.2),
+    (0.014, 1.29, 0.75, 0.2),
+    (0.027, 0.79, 0.125, -1.0),
+    (0.019, 1.41, 0.625, 1.0),
+    (0.031, 0.93, 0.375, -0.4),
+    (0.024, 1.07, 0.875, 0.4),
+];
+
+struct ChorusVoice {
+    delay: f64,
+    rate_ratio: f64,
+    left_gain: f64,
+    right_gain: f64,
+    lfo: Lfo,
+}
+
+impl ChorusVoice {
+    fn set_pan(&mut self, pan: f64) {
+        // Equal power, scaled so a centred voice has unity gain in both channels.
+        let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f64::consts::FRAC_PI_4;
+        self.left_gain = std::f64::consts::SQRT_2 * angle.cos();
+        self.right_gain = std::f64::consts::SQRT_2 * angle.sin();
+    }
+}
+
+/// Chorus made from up to `CHORUS_MAX_VOICES` modulated taps on a shared delay line.
+pub struct Chorus<S> {
+    delay_line: delay_line::DelayLine<Vec<Stereo<S>>>,
+    voices: Vec<ChorusVoice>,
+    active_voices: usize,
+    rate: f64,
+    depth: f64,
+    mix: f64,
+    sample_rate: f64,
+}
+
+impl<S: Sample> Chorus<S> {
+    pub fn new(voices: usize, sample_rate: usize) -> Self {
+        let capacity = (CHORUS_MAX_DELAY * sample_rate as f64).ceil() as usize + 4;
+
+        let mut chorus = Chorus {
+            delay_line: delay_line::DelayLine::new(vec![Stereo::<S>::EQUILIBRIUM; capacity], 0),
+            voices: CHORUS_DEFAULT_VOICES
+                .iter()
+                .map(|(delay, rate_ratio, phase, _)| {
+                    let mut lfo = Lfo::new(Waveform::Sine, 0.0, sample_rate);
+                    lfo.set_phase_offset(*phase);
+                    ChorusVoice {
+                        delay: delay * sample_rate as f64,
+                        rate_ratio: *rate_ratio,
+                        left_gain: 1.0,
+                        right_gain: 1.0,
+                        lfo,
+                    }
+                })
+                .collect(),
+            active_voices: 1,
+            rate: 0.0,
+            depth: 0.002 * sample_rate as f64,
+            mix: 0.5,
+            sample_rate: sample_rate as f64,
+        };
+
+        for (i, (_, _, _, pan)) in CHORUS_DEFAULT_VOICES.iter().enumerate() {
+            chorus.set_voice_pan(i, *pan);
+        }
+        chorus.set_voice_count(voices);
+        chorus.set_rate(0.5);
+
+        chorus
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        self.delay_line.tick(in_frame);
+
+        let voice_gain = 1.0 / self.active_voices as f64;
+        let mut wet = Stereo::<S>::EQUILIBRIUM;
+        for voice in self.voices.iter_mut().take(self.active_voices) {
+            let delay = voice.delay + self.depth * voice.lfo.tick();
+            let tap = self.delay_line.tap_cubic(delay);
+
+            let panned = [
+                tap[0].mul_amp((voice.left_gain * voice_gain).to_sample()),
+                tap[1].mul_amp((voice.right_gain * voice_gain).to_sample()),
+            ];
+            wet = wet.add_amp(panned.to_signed_frame());
+        }
+
+        in_frame
+            .scale_amp((1.0 - self.mix).to_sample())
+            .add_amp(wet.scale_amp(self.mix.to_sample()).to_signed_frame())
+    }
+
+    /// Number of voices used, from 1 to `CHORUS_MAX_VOICES`.
+    pub fn set_voice_count(&mut self, voices: usize) {
+        self.active_voices = voices.clamp(1, CHORUS_MAX_VOICES);
+    }
+
+    /// Sets the modulation rate of all voices. Each voice runs at its own ratio of this rate
+    /// so they do not move in lockstep.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate;
+        for voice in self.voices.iter_mut() {
+            voice.lfo.set_rate(rate * voice.rate_ratio);
+        }
+    }
+
+    /// Modulation depth in seconds, the same for all voices.
+    pub fn set_depth(&mut self, depth: f64) {
+        self.depth = depth * self.sample_rate;
+    }
+
+    /// Balance between dry (0) and chorused (1) signal.
+    pub fn set_mix(&mut self, mix: f64) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    /// Base delay of one voice in seconds.
+    pub fn set_voice_delay(&mut self, voice: usize, delay: f64) {
+        self.voices[voice].delay = delay.clamp(0.0, CHORUS_MAX_DELAY) * self.sample_rate;
+    }
+
+    /// Rate of one voice relative to the rate set with `set_rate`.
+    pub fn set_voice_rate_ratio(&mut self, voice: usize, ratio: f64) {
+        self.voices[voice].rate_ratio = ratio;
+        self.voices[voice].lfo.set_rate(self.rate * ratio);
+    }
+
+    /// LFO phase of one voice in cycles.
+    pub fn set_voice_phase(&mut self, voice: usize, phase: f64) {
+        self.voices[voice].lfo.set_phase_offset(phase);
+    }
+
+    /// Pan of one voice from -1 (left) to 1 (right).
+    pub fn set_voice_pan(&mut self, voice: usize, pan: f64) {
+        self.voices[voice].set_pan(pan);
+    }
+}
+
+pub const PHASER_MIN_STAGES: usize = 2;
+pub const PHASER_MAX_STAGES: usize = 12;
+
+/// Phaser made from a cascade of first order allpasses whose break frequency is swept by an LFO.
+///
+/// Every channel has its own LFO, each lagging the previous channel by the stereo offset.
+pub struct Phaser<T> {
+    stages: usize,
+    // The allpass states, `PHASER_MAX_STAGES` per channel.
+    states: Vec<f64>,
+    last_wet: Vec<f64>,
+    lfos: Vec<Lfo>,
+    min_frequency: f64,
+    max_frequency: f64,
+    depth: f64,
+    feedback: f64,
+    sample_rate: f64,
+    frame: std::marker::PhantomData<T>,
+}
+
+impl<T: Frame> Phaser<T> {
+    pub fn new(
+        stages: usize,
+        rate: f64,
+        min_frequency: f64,
+        max_frequency: f64,
+        sample_rate: usize,
+    ) -> Self {
+        let mut phaser = Phaser {
+            stages: PHASER_MIN_STAGES,
+            states: vec![0.0; PHASER_MAX_STAGES * T::CHANNELS],
+            last_wet: vec![0.0; T::CHANNELS],
+            lfos: (0..T::CHANNELS)
+                .map(|_| Lfo::new(Waveform::Sine, rate, sample_rate))
+                .collect(),
+            min_frequency: 0.0,
+            max_frequency: 0.0,
+            depth: 1.0,
+            feedback: 0.0,
+            sample_rate: sample_rate as f64,
+            frame: std::marker::PhantomData,
+        };
+        phaser.set_stages(stages);
+        phaser.set_range(min_frequency, max_frequency);
+        phaser
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        let stages = self.stages;
+        let depth = self.depth;
+        let feedback = self.feedback;
+        let sweep = self.max_frequency / self.min_frequency;
+        let min_frequency = self.min_frequency;
+        let sample_rate = self.sample_rate;
+        let states = &mut self.states;
+        let last_wet = &mut self.last_wet;
+        let lfos = &mut self.lfos;
+
+        T::from_fn(|channel| {
+            // Sweep exponentially, so the LFO moves evenly in pitch.
+            let position = (lfos[channel].tick() + 1.0) / 2.0;
+            let frequency = min_frequency * sweep.powf(position);
+            let t = (std::f64::consts::PI * frequency / sample_rate).tan();
+            let a = (t - 1.0) / (t + 1.0);
+
+            let dry = sample_to_f64(*in_frame.channel(channel).unwrap());
+            let mut x = dry + feedback * last_wet[channel];
+            for state in states
+                .iter_mut()
+                .skip(channel * PHASER_MAX_STAGES)
+                .take(stages)
+            {
+                let y = a * x + *state;
+                *state = x - a * y;
+                x = y;
+            }
+            last_wet[channel] = x;
+
+            sample_from_f64(dry + depth * x)
+        })
+    }
+
+    /// Number of allpass stages, from `PHASER_MIN_STAGES` to `PHASER_MAX_STAGES`. Every pair of
+    /// stages gives one notch.
+    pub fn set_stages(&mut self, stages: usize) {
+        self.stages = stages.clamp(PHASER_MIN_STAGES, PHASER_MAX_STAGES);
+    }
+
+    /// The range in Hz the break frequency of the allpasses is swept over.
+    pub fn set_range(&mut self, min_frequency: f64, max_frequency: f64) {
+        let nyquist = self.sample_rate / 2.0;
+        self.min_frequency = min_frequency.clamp(1.0, nyquist * 0.99);
+        self.max_frequency = max_frequency.clamp(self.min_frequency, nyquist * 0.99);
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_rate(rate);
+        }
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        for lfo in self.lfos.iter_mut() {
+            lfo.set_waveform(waveform);
+        }
+    }
+
+    /// LFO phase difference between neighbouring channels, in degrees from 0 to 180.
+    pub fn set_stereo_offset(&mut self, degrees: f64) {
+        let offset = degrees.clamp(0.0, 180.0) / 360.0;
+        for (channel, lfo) in self.lfos.iter_mut().enumerate() {
+            lfo.set_phase_offset(channel as f64 * offset);
+        }
+    }
+
+    /// Gain of the allpass output. 1 gives complete notches, -1 turns them into peaks.
+    pub fn set_depth(&mut self, depth: f64) {
+        self.depth = depth;
+    }
+
+    /// Clamped to `FLANGE_MAX_FEEDBACK` like the flanger.
+    pub fn set_feedback(&mut self, feedback: f64) {
+        self.feedback = feedback.clamp(-FLANGE_MAX_FEEDBACK, FLANGE_MAX_FEEDBACK);
+    }
+}
+
+/// An LFO with its output smoothed by a one pole lowpass, which takes the clicks out of the jumps
+/// in square, saw and sample and hold shapes.
+struct SmoothedLfo {
+    lfo: Lfo,
+    coefficient: f64,
+    value: f64,
+    sample_rate: f64,
+}
+
+impl SmoothedLfo {
+    fn new(waveform: Waveform, rate: f64, sample_rate: usize) -> Self {
+        let mut lfo = SmoothedLfo {
+            lfo: Lfo::new(waveform, rate, sample_rate),
+            coefficient: 1.0,
+            value: 0.0,
+            sample_rate: sample_rate as f64,
+        };
+        lfo.value = lfo.lfo.value();
+        lfo.set_smoothing(0.002);
+        lfo
+    }
+
+    /// Time constant of the smoothing in seconds, 0 turns it off.
+    fn set_smoothing(&mut self, time: f64) {
+        self.coefficient = if time > 0.0 {
+            1.0 - (-1.0 / (time * self.sample_rate)).exp()
+        } else {
+            1.0
+        };
+    }
+
+    fn set_rate_note(&mut self, note: f64, bpm: f64) {
+        self.lfo.set_rate(1.0 / note_duration(note, bpm));
+    }
+
+    fn tick(&mut self) -> f64 {
+        self.value += (self.lfo.tick() - self.value) * self.coefficient;
+        self.value
+    }
+}
+
+/// Amplitude modulation. At full depth the gain goes all the way down to silence.
+pub struct Tremolo<T> {
+    lfo: SmoothedLfo,
+    depth: f64,
+    frame: std::marker::PhantomData<T>,
+}
+
+impl<T: Frame> Tremolo<T> {
+    pub fn new(rate: f64, depth: f64, sample_rate: usize) -> Self {
+        Tremolo {
+            lfo: SmoothedLfo::new(Waveform::Sine, rate, sample_rate),
+            depth: depth.clamp(0.0, 1.0),
+            frame: std::marker::PhantomData,
+        }
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        let gain = 1.0 - self.depth * (1.0 - self.lfo.tick()) / 2.0;
+        in_frame.scale_amp(gain.to_sample())
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        self.lfo.lfo.set_rate(rate);
+    }
+
+    /// Syncs the rate to one cycle per note value (see `note_duration`) at the given tempo.
+    pub fn set_rate_note(&mut self, note: f64, bpm: f64) {
+        self.lfo.set_rate_note(note, bpm);
+    }
+
+    /// Between 0 and 1.
+    pub fn set_depth(&mut self, depth: f64) {
+        self.depth = depth.clamp(0.0, 1.0);
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.lfo.lfo.set_waveform(waveform);
+    }
+
+    /// Time constant in seconds for smoothing the modulation, 2 ms by default.
+    pub fn set_smoothing(&mut self, time: f64) {
+        self.lfo.set_smoothing(time);
+    }
+}
+
+/// Moves the signal between the channels with equal power panning.
+///
+/// The pan position is the modulation times the width. In the centre both channels are passed
+/// unchanged, and a signal in both channels keeps its power wherever it is panned.
+pub struct AutoPan<S> {
+    lfo: SmoothedLfo,
+    width: f64,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> AutoPan<S> {
+    pub fn new(rate: f64, width: f64, sample_rate: usize) -> Self {
+        AutoPan {
+            lfo: SmoothedLfo::new(Waveform::Sine, rate, sample_rate),
+            width: width.clamp(0.0, 1.0),
+            sample: std::marker::PhantomData,
+        }
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let pan = self.width * self.lfo.tick();
+        let angle = (pan + 1.0) * std::f64::consts::FRAC_PI_4;
+        let left = angle.cos() * std::f64::consts::SQRT_2;
+        let right = angle.sin() * std::f64::consts::SQRT_2;
+
+        [
+            sample_from_f64(sample_to_f64(in_frame[0]) * left),
+            sample_from_f64(sample_to_f64(in_frame[1]) * right),
+        ]
+    }
+
+    pub fn set_rate(&mut self, rate: f64) {
+        self.lfo.lfo.set_rate(rate);
+    }
+
+    /// Syncs the rate to one cycle per note value (see `note_duration`) at the given tempo.
+    pub fn set_rate_note(&mut self, note: f64, bpm: f64) {
+        self.lfo.set_rate_note(note, bpm);
+    }
+
+    /// Between 0 and 1, where 1 pans all the way to each side.
+    pub fn set_width(&mut self, width: f64) {
+        self.width = width.clamp(0.0, 1.0);
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.lfo.lfo.set_waveform(waveform);
+    }
+
+    /// Time constant in seconds for smoothing the modulation, 2 ms by default.
+    pub fn set_smoothing(&mut self, time: f64) {
+        self.lfo.set_smoothing(time);
+    }
+}
+
+/// Bit depth reduction and sample rate reduction.
+///
+/// The rate is reduced by holding each sample for `downsample` samples on average, which can be
+/// fractional, and the held samples are rounded to the bit depth, optionally with triangular
+/// dither. Both setters are cheap, so they can be modulated every sample.
+pub struct Bitcrusher<T> {
+    step: f64,
+    dither: bool,
+    downsample: f64,
+    // Where between two held samples we are, a new one is taken when it reaches 1.
+    phase: f64,
+    held: T,
+    rng: StdRng,
+}
+
+impl<T: Frame> Bitcrusher<T> {
+    pub fn new(bits: f64, downsample: f64) -> Self {
+        let mut crusher = Bitcrusher {
+            step: 0.0,
+            dither: false,
+            downsample: 1.0,
+            phase: 1.0,
+            held: T::EQUILIBRIUM,
+            rng: StdRng::seed_from_u64(0),
+        };
+        crusher.set_bits(bits);
+        crusher.set_downsample(downsample);
+        crusher
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        if self.phase >= 1.0 {
+            self.phase -= 1.0;
+
+            let step = self.step;
+            let dither = self.dither;
+            let rng = &mut self.rng;
+            let noise = Uniform::new_inclusive(-0.5, 0.5);
+            self.held = in_frame.map(|s| {
+                let mut x = sample_to_f64(s) / step;
+                if dither {
+                    x += noise.sample(rng) + noise.sample(rng);
+                }
+                sample_from_f64(x.round() * step)
+            });
+        }
+        self.phase += 1.0 / self.downsample;
+
+        self.held
+    }
+
+    /// Bits covering -1 to 1, fractional values give step sizes in between.
+    pub fn set_bits(&mut self, bits: f64) {
+        self.step = 2f64.powf(1.0 - bits.clamp(1.0, 32.0));
+    }
+
+    /// Adds triangular noise of plus or minus one step before rounding, which turns the
+    /// distortion of quiet signals into noise.
+    pub fn set_dither(&mut self, dither: bool) {
+        self.dither = dither;
+    }
+
+    /// Reseeds the dither, so it produces the same noise for the same seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// How many samples each held sample lasts, at least 1.
+    pub fn set_downsample(&mut self, downsample: f64) {
+        self.downsample = downsample.max(1.0);
+    }
+}
+
+/// PolyBLEP residual of a unit step at phase 0, with `dt` the phase increment per sample.
+fn poly_blep(phase: f64, dt: f64) -> f64 {
+    if phase < dt {
+        let x = phase / dt;
+        -(1.0 - x) * (1.0 - x) / 2.0
+    } else if phase > 1.0 - dt {
+        let x = (phase - 1.0) / dt;
+        (1.0 + x) * (1.0 + x) / 2.0
+    } else {
+        0.0
+    }
+}
+
+/// PolyBLAMP residual of a change in slope of one per sample at phase 0, the integral of
+/// `poly_blep`.
+fn poly_blamp(phase: f64, dt: f64) -> f64 {
+    if phase < dt {
+        (1.0 - phase / dt).powi(3) / 6.0
+    } else if phase > 1.0 - dt {
+        (1.0 + (phase - 1.0) / dt).powi(3) / 6.0
+    } else {
+        0.0
+    }
+}
+
+/// Multiplies the input by a carrier, either an internal oscillator or a second input.
+///
+/// In AM mode the carrier is offset to swing between 0 and 1, so the input itself stays in the
+/// output alongside the sidebands. The oscillator runs at audio rates, so the corners of the
+/// square, saw and triangle are smoothed with PolyBLEP and PolyBLAMP to keep aliasing down.
+pub struct RingMod<T> {
+    oscillator: Lfo,
+    mix: f64,
+    am: bool,
+    sample_rate: f64,
+    frame: std::marker::PhantomData<T>,
+}
+
+impl<T: Frame> RingMod<T> {
+    pub fn new(frequency: f64, sample_rate: usize) -> Self {
+        RingMod {
+            oscillator: Lfo::new(Waveform::Sine, frequency, sample_rate),
+            mix: 1.0,
+            am: false,
+            sample_rate: sample_rate as f64,
+            frame: std::marker::PhantomData,
+        }
+    }
+
+    /// Modulates with the internal oscillator.
+    pub fn tick(&mut self, in_frame: T) -> T {
+        let carrier = self.carrier();
+        self.oscillator.tick();
+        self.modulate(in_frame, |_| carrier)
+    }
+
+    /// The oscillator output at its current phase, band limited where the waveform has corners.
+    fn carrier(&self) -> f64 {
+        let naive = self.oscillator.value();
+        let phase = self.oscillator.get_phase();
+        let dt = (self.oscillator.get_rate() / self.sample_rate)
+            .abs()
+            .min(0.5);
+        if dt == 0.0 {
+            return naive;
+        }
+
+        match self.oscillator.get_waveform() {
+            Waveform::Square => {
+                naive + 2.0 * poly_blep(phase, dt) - 2.0 * poly_blep((phase + 0.5).fract(), dt)
+            }
+            Waveform::Saw => naive - 2.0 * poly_blep(phase, dt),
+            // The slope of 4 per cycle turns around at the peak and the trough.
+            Waveform::Triangle => {
+                let turn = 8.0 * dt;
+                naive - turn * poly_blamp((phase + 0.75).fract(), dt)
+                    + turn * poly_blamp((phase + 0.25).fract(), dt)
+            }
+            _ => naive,
+        }
+    }
+
+    /// Modulates each channel with the same channel of `carrier`, leaving the oscillator alone.
+    pub fn tick_with_carrier(&mut self, in_frame: T, carrier: T) -> T {
+        self.modulate(in_frame, |channel| {
+            sample_to_f64(*carrier.channel(channel).unwrap())
+        })
+    }
+
+    fn modulate(&self, in_frame: T, carrier: impl Fn(usize) -> f64) -> T {
+        let mut channel = 0;
+        in_frame.map(|s| {
+            let mut c = carrier(channel);
+            channel += 1;
+            if self.am {
+                c = (1.0 + c) / 2.0;
+            }
+            let dry = sample_to_f64(s);
+            sample_from_f64(dry * (1.0 - self.mix) + dry * c * self.mix)
+        })
+    }
+
+    /// Oscillator frequency in Hz.
+    pub fn set_frequency(&mut self, frequency: f64) {
+        self.oscillator.set_rate(frequency);
+    }
+
+    /// Oscillator shape, usually sine, square or triangle.
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.oscillator.set_waveform(waveform);
+    }
+
+    /// Between 0 for only the input and 1 for only the modulated signal.
+    pub fn set_mix(&mut self, mix: f64) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn set_am(&mut self, am: bool) {
+        self.am = am;
+    }
+}
+
+/// Bandpass filter swept by the loudness of the input, or by an LFO.
+///
+/// The envelope, turned up by the sensitivity and capped at 1, moves the centre frequency
+/// exponentially from the bottom to the top of the range, so playing harder opens the wah
+/// further. In LFO mode the envelope is ignored and the LFO sweeps the whole range instead.
+pub struct AutoWah<T> {
+    follower: dynamics::EnvelopeFollower,
+    lfo: SmoothedLfo,
+    lfo_mode: bool,
+    sensitivity: f64,
+    low: f64,
+    high: f64,
+    resonance: f64,
+    mix: f64,
+    frequency: f64,
+    filter: Biquad<T>,
+    sample_rate: usize,
+}
+
+impl<T: Frame> AutoWah<T> {
+    pub fn new(sample_rate: usize) -> Self {
+        let mut wah = AutoWah {
+            follower: dynamics::EnvelopeFollower::new(
+                dynamics::Detection::Peak,
+                0.005,
+                0.1,
+                sample_rate,
+            ),
+            lfo: SmoothedLfo::new(Waveform::Sine, 1.0, sample_rate),
+            lfo_mode: false,
+            sensitivity: 10.0,
+            low: 300.0,
+            high: 2500.0,
+            resonance: 4.0,
+            mix: 1.0,
+            frequency: 300.0,
+            filter: Biquad::new(BiquadCoefficients::bandpass(300.0, 4.0, sample_rate)),
+            sample_rate,
+        };
+        wah.set_range(300.0, 2500.0);
+        wah
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        let control = if self.lfo_mode {
+            (1.0 + self.lfo.tick()) / 2.0
+        } else {
+            let level = in_frame
+                .channels()
+                .map(|s| sample_to_f64(s).abs())
+                .fold(0.0, f64::max);
+            (self.follower.tick(level) * self.sensitivity).min(1.0)
+        };
+
+        self.frequency = self.low * (self.high / self.low).powf(control);
+        self.filter.set_coefficients(BiquadCoefficients::bandpass(
+            self.frequency,
+            self.resonance,
+            self.sample_rate,
+        ));
+        let wet = self.filter.tick(in_frame);
+
+        in_frame
+            .scale_amp((1.0 - self.mix).to_sample())
+            .add_amp(wet.scale_amp(self.mix.to_sample()).to_signed_frame())
+    }
+
+    /// Current centre frequency in Hz.
+    pub fn frequency(&self) -> f64 {
+        self.frequency
+    }
+
+    /// Gain in dB applied to the envelope, the higher the less it takes to open the wah fully.
+    pub fn set_sensitivity(&mut self, sensitivity: f64) {
+        self.sensitivity = 10f64.powf(sensitivity / 20.0);
+    }
+
+    /// In seconds.
+    pub fn set_attack(&mut self, attack: f64) {
+        self.follower.set_attack(attack);
+    }
+
+    /// In seconds.
+    pub fn set_release(&mut self, release: f64) {
+        self.follower.set_release(release);
+    }
+
+    /// Lowest and highest centre frequencies in Hz, kept below Nyquist. They are swapped if given
+    /// the wrong way round, so a louder input always opens the wah further.
+    pub fn set_range(&mut self, low: f64, high: f64) {
+        let nyquist = self.sample_rate as f64 / 2.0;
+        let (low, high) = if low > high { (high, low) } else { (low, high) };
+        self.low = low.clamp(1.0, 0.95 * nyquist);
+        self.high = high.clamp(1.0, 0.95 * nyquist);
+    }
+
+    /// Q of the bandpass.
+    pub fn set_resonance(&mut self, resonance: f64) {
+        self.resonance = resonance.max(0.1);
+    }
+
+    /// Sweeps with the LFO instead of the envelope.
+    pub fn set_lfo(&mut self, lfo: bool) {
+        self.lfo_mode = lfo;
+    }
+
+    pub fn set_lfo_rate(&mut self, rate: f64) {
+        self.lfo.lfo.set_rate(rate);
+    }
+
+    pub fn set_lfo_waveform(&mut self, waveform: Waveform) {
+        self.lfo.lfo.set_waveform(waveform);
+    }
+
+    /// Between 0 for only the input and 1 for only the filtered signal.
+    pub fn set_mix(&mut self, mix: f64) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Times in samples of the rising zero crossings of `out` between `from` and `to`,
+    /// interpolated between the samples on either side.
+    pub(super) fn rising_zero_crossings(out: &[f64], from: usize, to: usize) -> Vec<f64> {
+        (from..to.min(out.len() - 1))
+            .filter(|n| out[*n] < 0.0 && out[n + 1] >= 0.0)
+            .map(|n| n as f64 + out[n] / (out[n] - out[n + 1]))
+            .collect()
+    }
+
+    /// Average frequency of `out` between `from` and `to`, from its rising zero crossings.
+    pub(super) fn measure_frequency(
+        out: &[f64],
+        from: usize,
+        to: usize,
+        sample_rate: usize,
+    ) -> f64 {
+        let crossings = rising_zero_crossings(out, from, to);
+        (crossings.len() - 1) as f64 * sample_rate as f64
+            / (crossings[crossings.len() - 1] - crossings[0])
+    }
+
+    fn impulse_response<T: Frame>(effect: &mut Flange<T>, length: usize, one: T) -> Vec<T> {
+        (0..length)
+            .map(|n| effect.tick(if n == 0 { one } else { T::EQUILIBRIUM }))
+            .collect()
+    }
+
+    #[test]
+    pub fn flange_feedback_repeats() {
+        // With a rate of zero the delay stays at the centre, here 10 samples.
+        for feedback in [0.5, -0.5].iter() {
+            let mut flange = Flange::<f64>::new(0.0, 0.01, 1.0, 1000);
+            flange.set_feedback(*feedback);
+            let response = impulse_response(&mut flange, 40, 1.0);
+
+            // The loop is one sample longer than the delay since the feedback is taken from the
+            // previous output.
+            assert_relative_eq!(response[0], 1.0);
+            assert_relative_eq!(response[10], 1.0, epsilon = 1e-3);
+            assert_relative_eq!(response[21], *feedback, epsilon = 1e-3);
+            assert_relative_eq!(response[32], feedback * feedback, epsilon = 1e-3);
+        }
+    }
+
+    #[test]
+    pub fn flange_feedback_is_clamped() {
+        let mut flange = Flange::<f64>::new(0.0, 0.01, 1.0, 1000);
+        flange.set_feedback(3.0);
+        let response = impulse_response(&mut flange, 40, 1.0);
+
+        assert_relative_eq!(response[21], FLANGE_MAX_FEEDBACK, epsilon = 1e-3);
+    }
+
+    #[test]
+    pub fn flange_through_zero_cancels() {
+        // At the centre of the sweep an inverted wet signal cancels the delayed dry signal.
+        let mut flange = Flange::<[f64; 2]>::new(0.0, 0.002, -1.0, 48000);
+        flange.set_through_zero(true);
+
+        for n in 0..1000 {
+            let x = (n as f64 * 0.1).sin();
+            let out = flange.tick([x, -x]);
+            assert_relative_eq!(out[0], 0.0, epsilon = 1e-12);
+            assert_relative_eq!(out[1], 0.0, epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    pub fn stereo_flange_spread() {
+        let mut flange = StereoFlange::<f64>::new(1.0, 0.002, 0.7, 48000);
+        for n in 0..10000 {
+            let x = (n as f64 * 0.05).sin();
+            let out = flange.tick([x, x]);
+            assert_relative_eq!(out[0], out[1]);
+        }
+
+        let mut flange = StereoFlange::<f64>::new(1.0, 0.002, 0.7, 48000);
+        flange.set_spread(180.0);
+        let mut difference = 0.0;
+        for n in 0..10000 {
+            let x = (n as f64 * 0.05).sin();
+            let out = flange.tick([x, x]);
+            difference += (out[0] - out[1]).abs();
+        }
+        assert!(difference > 1.0);
+    }
+
+    #[test]
+    pub fn stereo_flange_cross_feedback() {
+        let mut flange = StereoFlange::<f64>::new(0.0, 0.01, 1.0, 1000);
+        flange.set_feedback(0.5);
+        flange.set_cross_feedback(1.0);
+
+        let response: Vec<_> = (0..40)
+            .map(|n| flange.tick(if n == 0 { [1.0, 0.0] } else { [0.0, 0.0] }))
+            .collect();
+
+        // The first repeat of the left impulse regenerates in the right channel only.
+        assert_relative_eq!(response[10][0], 1.0, epsilon = 1e-3);
+        assert_relative_eq!(response[10][1], 0.0, epsilon = 1e-3);
+        assert_relative_eq!(response[21][0], 0.0, epsilon = 1e-3);
+        assert_relative_eq!(response[21][1], 0.5, epsilon = 1e-3);
+        assert_relative_eq!(response[32][0], 0.25, epsilon = 1e-3);
+    }
+
+    #[test]
+    pub fn chorus_single_voice_delay() {
+        // A single centred voice without modulation is just a delay.
+        let sample_rate = 1000;
+        let mut chorus = Chorus::<f64>::new(1, sample_rate);
+        chorus.set_voice_delay(0, 0.01);
+        chorus.set_voice_pan(0, 0.0);
+        chorus.set_depth(0.0);
+        chorus.set_mix(1.0);
+
+        for n in 0..100 {
+            let x = n as f64;
+            let out = chorus.tick([x, -x]);
+            if n >= 10 {
+                assert_relative_eq!(out[0], x - 10.0, epsilon = 1e-9);
+                assert_relative_eq!(out[1], 10.0 - x, epsilon = 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    pub fn chorus_dry() {
+        let mut chorus = Chorus::<f32>::new(CHORUS_MAX_VOICES, 48000);
+        chorus.set_mix(0.0);
+
+        for n in 0..1000 {
+            let x = (n as f32 * 0.1).sin();
+            assert_eq!(chorus.tick([x, 0.5 * x]), [x, 0.5 * x]);
+        }
+    }
+
+    #[test]
+    pub fn phaser_notch_count() {
+        let sample_rate = 48000;
+
+        for stages in [2, 4, 6, 8, 12].iter() {
+            // Without modulation the allpasses sit at the geometric centre of the range.
+            let mut phaser = Phaser::<f64>::new(*stages, 0.0, 200.0, 5000.0, sample_rate);
+            let response: Vec<f64> = (0..2048)
+                .map(|n| phaser.tick(if n == 0 { 1.0 } else { 0.0 }))
+                .collect();
+
+            // Log spaced from 20 Hz to 20 kHz, so the narrow low notches are resolved too.
+            let magnitudes: Vec<f64> = (0..2000)
+                .map(|i| {
+                    let f = 20.0 * 1000f64.powf(i as f64 / 2000.0);
+                    let w = 2.0 * std::f64::consts::PI * f / sample_rate as f64;
+                    // Horner's scheme in e^-jw
+                    let (c, s) = (w.cos(), -w.sin());
+                    let (re, im) = response.iter().rev().fold((0.0, 0.0), |(re, im), h| {
+                        (h + re * c - im * s, re * s + im * c)
+                    });
+                    (re * re + im * im).sqrt()
+                })
+                .collect();
+
+            let notches = magnitudes
+                .windows(3)
+                .filter(|m| m[1] < m[0] && m[1] < m[2] && m[1] < 0.5)
+                .count();
+
+            assert_eq!(notches, stages / 2);
+        }
+    }
+
+    #[test]
+    pub fn phaser_stereo_offset() {
+        let mut phaser = Phaser::<[f32; 2]>::new(4, 1.0, 200.0, 5000.0, 48000);
+        phaser.set_stereo_offset(90.0);
+
+        let mut difference = 0.0;
+        for n in 0..48000 {
+            let x = (n as f32 * 0.05).sin();
+            let out = phaser.tick([x, x]);
+            difference += (out[0] - out[1]).abs();
+        }
+        assert!(difference > 1.0);
+    }
+
+    #[test]
+    pub fn echo_feedback_repeats() {
+        let mut echo = Echo::<f64>::with_delay_ms(10.0, 1.0, 1000, 100);
+        echo.set_feedback(0.5);
+
+        let response: Vec<f64> = (0..40)
+            .map(|n| echo.tick(if n == 0 { 1.0 } else { 0.0 }))
+            .collect();
+
+        for (n, v) in response.iter().enumerate() {
+            let expected = match n {
+                0 => 1.0,
+                10 => 1.0,
+                20 => 0.5,
+                30 => 0.25,
+                _ => 0.0,
+            };
+            assert_relative_eq!(*v, expected);
+        }
+    }
+
+    #[test]
+    pub fn echo_note_delay() {
+        // A sixteenth at 120 bpm is 125 ms.
+        assert_relative_eq!(note_duration(0.25, 120.0), 0.5);
+        let mut echo = Echo::<f64>::with_delay_ms(0.0, 1.0, 1000, 1000);
+        echo.set_delay_note(1.0 / 16.0, 120.0);
+
+        let response: Vec<f64> = (0..200)
+            .map(|n| echo.tick(if n == 0 { 1.0 } else { 0.0 }))
+            .collect();
+        assert_relative_eq!(response[125], 1.0);
+        assert_relative_eq!(response[1..125].iter().sum::<f64>(), 0.0);
+    }
+
+    #[test]
+    pub fn echo_ping_pong() {
+        let mut echo = Echo::<[f64; 2]>::with_delay_ms(10.0, 1.0, 1000, 100);
+        echo.set_feedback(0.5);
+        echo.set_ping_pong(true);
+
+        let response: Vec<[f64; 2]> = (0..40)
+            .map(|n| echo.tick(if n == 0 { [1.0, 1.0] } else { [0.0, 0.0] }))
+            .collect();
+
+        assert_eq!(response[10], [1.0, 0.0]);
+        assert_eq!(response[20], [0.0, 0.5]);
+        assert_eq!(response[30], [0.25, 0.0]);
+    }
+
+    #[test]
+    pub fn echo_filtered_repeats_decay() {
+        // A lowpass in the loop makes a high tone die away faster than a low one.
+        let energy_after = |frequency: f64| {
+            let mut echo = Echo::<f64>::with_delay_ms(50.0, 1.0, 48000, 48000);
+            echo.set_feedback(0.9);
+            echo.set_lowpass(Some(2000.0));
+            echo.set_saturation(true);
+
+            let w = 2.0 * std::f64::consts::PI * frequency / 48000.0;
+            (0..48000)
+                .map(|n| {
+                    let x = if n < 2400 {
+                        0.5 * (w * n as f64).sin()
+                    } else {
+                        0.0
+                    };
+                    echo.tick(x)
+                })
+                .skip(24000)
+                .map(|v| v * v)
+                .sum::<f64>()
+        };
+
+        assert!(energy_after(8000.0) < 0.01 * energy_after(200.0));
+    }
+
+    #[test]
+    pub fn moving_source_static_paths() {
+        // A ramp goes through the cubic interpolation unchanged, so the output is exactly the
+        // two delayed and attenuated ramps.
+        let sample_rate = 48000;
+        let (d, h) = (20.0, 1.5);
+        let mut source =
+            MovingSource::<f64>::new(vec![(0.0, [0.0, 0.0, h])], [d, 0.0, h], sample_rate);
+
+        let reflected = (d * d + 4.0 * h * h).sqrt();
+        let direct_delay = d / room::SPEED_OF_SOUND * sample_rate as f64;
+        let reflected_delay = reflected / room::SPEED_OF_SOUND * sample_rate as f64;
+
+        for n in 0..10000 {
+            let out = source.tick(n as f64);
+            if n > 4000 {
+                let expected =
+                    (n as f64 - direct_delay) / d + (n as f64 - reflected_delay) / reflected;
+                assert_relative_eq!(out, expected, epsilon = 1e-6);
+            }
+        }
+    }
+
+    /// Pitch deviation in cents of each period of the vibrato's output for a sine input.
+    fn vibrato_deviation(depth: f64, rate: f64) -> Vec<f64> {
+        let sample_rate = 48000;
+        let frequency = 1000.0;
+        let mut vibrato = Vibrato::<f64>::new(depth, rate, sample_rate);
+
+        let w = 2.0 * std::f64::consts::PI * frequency / sample_rate as f64;
+        let out: Vec<f64> = (0..sample_rate)
+            .map(|n| vibrato.tick((w * n as f64).sin()))
+            .collect();
+
+        rising_zero_crossings(&out, 100, out.len())
+            .windows(2)
+            .map(|c| 1200.0 * (sample_rate as f64 / (c[1] - c[0]) / frequency).log2())
+            .collect()
+    }
+
+    #[test]
+    pub fn vibrato_pitch_deviation() {
+        for (depth, rate) in [(50.0, 5.0), (20.0, 2.0), (100.0, 7.0)].iter() {
+            let deviation = vibrato_deviation(*depth, *rate);
+            let highest = deviation.iter().cloned().fold(f64::MIN, f64::max);
+            let lowest = deviation.iter().cloned().fold(f64::MAX, f64::min);
+
+            assert_relative_eq!(highest, *depth, epsilon = 0.5);
+            // The delay changes as fast in both directions, which is not quite symmetric in cents.
+            let expected_lowest = 1200.0 * (2.0 - 2f64.powf(depth / 1200.0)).log2();
+            assert_relative_eq!(lowest, expected_lowest, epsilon = 0.5);
+        }
+    }
+
+    #[test]
+    pub fn vibrato_without_depth_delays() {
+        let mut vibrato = Vibrato::<f64>::new(30.0, 5.0, 48000);
+        vibrato.set_depth(0.0);
+        let latency = vibrato.latency() as usize;
+
+        for n in 0..100 {
+            let out = vibrato.tick(n as f64);
+            assert_relative_eq!(out, n.max(latency) as f64 - latency as f64);
+        }
+    }
+
+    fn shifted_sine(shifter: &mut PitchShifter<f64>, frequency: f64, length: usize) -> Vec<f64> {
+        let w = 2.0 * std::f64::consts::PI * frequency / 48000.0;
+        (0..length)
+            .map(|n| shifter.tick((w * n as f64).sin()))
+            .collect()
+    }
+
+    #[test]
+    pub fn pitch_shifter_frequency() {
+        for (semitones, cents) in [(7.0, 0.0), (-12.0, 0.0), (2.0, -30.0)].iter() {
+            let mut shifter = PitchShifter::<f64>::new(*semitones, *cents, 48000);
+            let out = shifted_sine(&mut shifter, 440.0, 48000);
+
+            let expected = 440.0 * 2f64.powf((semitones * 100.0 + cents) / 1200.0);
+            assert_relative_eq!(
+                measure_frequency(&out, 10000, out.len(), 48000),
+                expected,
+                max_relative = 2e-3
+            );
+        }
+    }
+
+    #[test]
+    pub fn pitch_shifter_unshifted_delays() {
+        let mut shifter = PitchShifter::<f64>::new(0.0, 0.0, 48000);
+        let latency = shifter.latency() as usize;
+
+        for n in 0..3000 {
+            let out = shifter.tick(n as f64);
+            assert_relative_eq!(out, n.max(latency) as f64 - latency as f64, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn pitch_shifter_splice_search() {
+        // Without lining up the heads a shifted sine partially cancels in the crossfades.
+        let amplitude_range = |search: f64| {
+            let mut shifter = PitchShifter::<f64>::new(3.0, 0.0, 48000);
+            shifter.set_search(search);
+            let out = shifted_sine(&mut shifter, 300.0, 48000);
+
+            // Peaks of each 10 ms block, a few periods.
+            let peaks: Vec<f64> = out[10000..]
+                .chunks(480)
+                .map(|c| c.iter().cloned().fold(0.0, |a: f64, b| a.max(b.abs())))
+                .collect();
+            let lowest = peaks.iter().cloned().fold(f64::MAX, f64::min);
+            let highest = peaks.iter().cloned().fold(0.0, f64::max);
+            highest - lowest
+        };
+
+        assert!(amplitude_range(0.0) > 0.2);
+        assert!(amplitude_range(0.01) < 0.05);
+        // The coarse pass of the widest search skips the most offsets.
+        assert!(amplitude_range(PITCH_SHIFTER_MAX_SEARCH) < 0.05);
+    }
+
+    #[test]
+    pub fn pitch_shifter_stereo() {
+        let mut shifter = PitchShifter::<[f32; 2]>::new(12.0, 0.0, 48000);
+        let w = 2.0 * std::f64::consts::PI * 200.0 / 48000.0;
+        let out: Vec<[f32; 2]> = (0..20000)
+            .map(|n| {
+                let x = (w * n as f64).sin() as f32;
+                shifter.tick([x, -x])
+            })
+            .collect();
+
+        assert!(out.iter().all(|o| (o[0] + o[1]).abs() < 1e-5));
+        let left: Vec<f64> = out.iter().map(|o| o[0] as f64).collect();
+        assert_relative_eq!(
+            measure_frequency(&left, 5000, left.len(), 48000),
+            400.0,
+            max_relative = 2e-3
+        );
+    }
+
+    #[test]
+    pub fn tremolo_depth() {
+        let mut tremolo = Tremolo::<f64>::new(10.0, 0.5, 1000);
+        tremolo.set_smoothing(0.0);
+        let gains: Vec<f64> = (0..1000).map(|_| tremolo.tick(1.0)).collect();
+
+        let lowest = gains.iter().cloned().fold(f64::MAX, f64::min);
+        let highest = gains.iter().cloned().fold(f64::MIN, f64::max);
+        assert_relative_eq!(lowest, 0.5, epsilon = 1e-3);
+        assert_relative_eq!(highest, 1.0, epsilon = 1e-3);
+    }
+
+    #[test]
+    pub fn tremolo_tempo_sync() {
+        // Quarter notes at 120 bpm are 2 Hz, so the square wave flips every 250 ms.
+        let mut tremolo = Tremolo::<f64>::new(1.0, 1.0, 1000);
+        tremolo.set_waveform(Waveform::Square);
+        tremolo.set_smoothing(0.0);
+        tremolo.set_rate_note(0.25, 120.0);
+        let gains: Vec<f64> = (0..1000).map(|_| tremolo.tick(1.0)).collect();
+
+        for (n, gain) in gains.iter().enumerate() {
+            let expected = if (n / 250) % 2 == 0 { 1.0 } else { 0.0 };
+            assert_relative_eq!(*gain, expected);
+        }
+    }
+
+    #[test]
+    pub fn tremolo_square_smoothing() {
+        let largest_step = |smoothing: f64| {
+            let mut tremolo = Tremolo::<f64>::new(2.0, 1.0, 48000);
+            tremolo.set_waveform(Waveform::Square);
+            tremolo.set_smoothing(smoothing);
+            let gains: Vec<f64> = (0..48000).map(|_| tremolo.tick(1.0)).collect();
+            gains
+                .windows(2)
+                .map(|w| (w[1] - w[0]).abs())
+                .fold(0.0, f64::max)
+        };
+
+        assert_relative_eq!(largest_step(0.0), 1.0);
+        // A 2 ms time constant at 48 kHz moves at most 1 / 96 of the way per sample.
+        assert!(largest_step(0.002) < 0.011);
+    }
+
+    #[test]
+    pub fn auto_pan_equal_power() {
+        let mut pan = AutoPan::<f64>::new(3.0, 1.0, 1000);
+        pan.set_smoothing(0.0);
+        let out: Vec<[f64; 2]> = (0..1000).map(|_| pan.tick([1.0, 1.0])).collect();
+
+        for o in out.iter() {
+            assert_relative_eq!(o[0] * o[0] + o[1] * o[1], 2.0, epsilon = 1e-9);
+        }
+        // Hard left and hard right at the peaks of the sine.
+        assert!(out.iter().any(|o| o[1] < 1e-3));
+        assert!(out.iter().any(|o| o[0] < 1e-3));
+    }
+
+    #[test]
+    pub fn auto_pan_without_width() {
+        let mut pan = AutoPan::<f32>::new(3.0, 0.0, 1000);
+        for n in 0..100 {
+            let x = n as f32 / 100.0;
+            let out = pan.tick([x, -x]);
+            assert_relative_eq!(out[0], x, epsilon = 1e-6);
+            assert_relative_eq!(out[1], -x, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    pub fn bitcrusher_quantizes() {
+        let mut crusher = Bitcrusher::<f64>::new(3.0, 1.0);
+        for n in 0..100 {
+            let x = n as f64 / 50.0 - 1.0;
+            let out = crusher.tick(x);
+            assert_relative_eq!(out, (x * 4.0).round() / 4.0);
+        }
+    }
+
+    #[test]
+    pub fn bitcrusher_dither_averages_out() {
+        // 0.1 is between the steps of 0 and 0.25. Without dither it always rounds down.
+        let mut crusher = Bitcrusher::<f64>::new(3.0, 1.0);
+        assert_relative_eq!(crusher.tick(0.1), 0.0);
+
+        crusher.set_dither(true);
+        crusher.set_seed(7);
+        let outputs: Vec<f64> = (0..100000).map(|_| crusher.tick(0.1)).collect();
+        assert!(outputs.iter().all(|o| (o / 0.25).fract() == 0.0));
+        let mean = outputs.iter().sum::<f64>() / outputs.len() as f64;
+        assert_relative_eq!(mean, 0.1, epsilon = 2e-3);
+    }
+
+    #[test]
+    pub fn bitcrusher_fractional_hold() {
+        let mut crusher = Bitcrusher::<[f32; 2]>::new(32.0, 2.5);
+        let out: Vec<[f32; 2]> = (0..1000)
+            .map(|n| crusher.tick([n as f32, -(n as f32)]))
+            .collect();
+
+        // A new sample every 2.5 samples on average, so alternately held for 3 and 2.
+        let changes: Vec<usize> = (1..out.len()).filter(|n| out[*n] != out[n - 1]).collect();
+        assert_eq!(changes.len(), 399);
+        assert!(changes
+            .windows(2)
+            .all(|w| w[1] - w[0] == 2 || w[1] - w[0] == 3));
+        for (n, o) in out.iter().enumerate() {
+            assert!(o[0] <= n as f32);
+            assert_eq!(o[0], -o[1]);
+        }
+    }
+
+    #[test]
+    pub fn bitcrusher_modulated_every_sample() {
+        let mut lfo = Lfo::new(Waveform::Triangle, 5.0, 1000);
+
+        // Every sample is rounded with the bit depth set for it.
+        let mut crusher = Bitcrusher::<f64>::new(16.0, 1.0);
+        for n in 0..1000 {
+            let bits = 6.0 + 2.0 * lfo.tick().round();
+            crusher.set_bits(bits);
+            let out = crusher.tick((n as f64 * 0.01).sin());
+            assert_relative_eq!((out / 2f64.powf(1.0 - bits)).fract(), 0.0);
+        }
+
+        // Samples are taken as often as the changing downsampling says.
+        let mut crusher = Bitcrusher::<f64>::new(32.0, 1.0);
+        let mut expected_changes = 0.0;
+        let mut changes = 0;
+        let mut previous = -1.0;
+        for n in 0..1000 {
+            let downsample = 3.0 + 2.0 * lfo.tick();
+            crusher.set_downsample(downsample);
+            expected_changes += 1.0 / downsample;
+
+            let out = crusher.tick(n as f64 / 1000.0);
+            if out != previous {
+                changes += 1;
+            }
+            previous = out;
+        }
+        assert!((changes as f64 - expected_changes).abs() < 2.0);
+    }
+
+    #[test]
+    pub fn ring_mod_oscillator() {
+        // A constant input comes out as the carrier.
+        let mut ring = RingMod::<f64>::new(440.0, 48000);
+        let w = 2.0 * std::f64::consts::PI * 440.0 / 48000.0;
+        for n in 0..1000 {
+            assert_relative_eq!(ring.tick(0.5), 0.5 * (w * n as f64).sin(), epsilon = 1e-4);
+        }
+
+        // Away from the smoothed edges the square is flat.
+        ring.set_waveform(Waveform::Square);
+        let out: Vec<f64> = (0..1000).map(|_| ring.tick(0.5)).collect();
+        assert!(out.iter().all(|o| o.abs() <= 0.5));
+        let flat = out.iter().filter(|o| o.abs() == 0.5).count();
+        assert!(flat > 1000 - 2 * 2 * 10);
+    }
+
+    /// Fraction in dB of the power of a ring modulated constant, which is just the carrier, that
+    /// is not at the carrier's harmonics.
+    fn carrier_aliasing(waveform: Waveform, band_limited: bool) -> f64 {
+        let size = 4096;
+        // Exactly on a bin, so the harmonics that do not alias land on bins too.
+        let bin = 100;
+        let frequency = bin as f64 * 48000.0 / size as f64;
+        let mut ring = RingMod::<f64>::new(frequency, 48000);
+        ring.set_waveform(waveform);
+        let mut naive = Lfo::new(waveform, frequency, 48000);
+
+        let mut spectrum: Vec<crate::fft::Complex> = (0..size)
+            .map(|_| {
+                let carrier = if band_limited {
+                    ring.tick(1.0)
+                } else {
+                    naive.tick()
+                };
+                crate::fft::Complex::new(carrier, 0.0)
+            })
+            .collect();
+        crate::fft::Fft::new(size).forward(&mut spectrum);
+
+        let power = |k: usize| spectrum[k].norm().powi(2);
+        let total: f64 = (1..size / 2).map(power).sum();
+        let harmonics: f64 = (1..)
+            .map(|h| h * bin)
+            .take_while(|k| *k < size / 2)
+            .map(power)
+            .sum();
+        10.0 * ((total - harmonics) / total).log10()
+    }
+
+    #[test]
+    pub fn ring_mod_band_limited_carriers() {
+        for waveform in [Waveform::Square, Waveform::Saw, Waveform::Triangle].iter() {
+            let naive = carrier_aliasing(*waveform, false);
+            let band_limited = carrier_aliasing(*waveform, true);
+            assert!(band_limited < naive - 10.0);
+        }
+    }
+
+    #[test]
+    pub fn ring_mod_sidebands() {
+        let size = 4096;
+        let (input_bin, carrier_bin) = (300, 100);
+        let w = |bin: usize| 2.0 * std::f64::consts::PI * bin as f64 / size as f64;
+
+        let spectrum = |am: bool| {
+            let mut ring = RingMod::<f64>::new(carrier_bin as f64, size);
+            ring.set_am(am);
+            let mut spectrum: Vec<crate::fft::Complex> = (0..size)
+                .map(|n| crate::fft::Complex::new(ring.tick((w(input_bin) * n as f64).sin()), 0.0))
+                .collect();
+            crate::fft::Fft::new(size).forward(&mut spectrum);
+            spectrum
+                .iter()
+                .map(|c| c.norm() / size as f64)
+                .collect::<Vec<f64>>()
+        };
+
+        // Only the sum and difference frequencies, each at a quarter of the input.
+        let ring = spectrum(false);
+        for (bin, level) in ring.iter().enumerate().take(size / 2) {
+            let expected = if bin == 200 || bin == 400 { 0.25 } else { 0.0 };
+            assert_relative_eq!(*level, expected, epsilon = 1e-4);
+        }
+
+        // AM keeps the input at half level, with the sidebands at half their level.
+        let am = spectrum(true);
+        assert_relative_eq!(am[300], 0.25, epsilon = 1e-4);
+        assert_relative_eq!(am[200], 0.125, epsilon = 1e-4);
+        assert_relative_eq!(am[400], 0.125, epsilon = 1e-4);
+    }
+
+    #[test]
+    pub fn ring_mod_carrier_input() {
+        let mut ring = RingMod::<[f32; 2]>::new(440.0, 48000);
+        ring.set_mix(0.5);
+        for n in 0..100 {
+            let x = n as f32 / 100.0;
+            let out = ring.tick_with_carrier([x, x], [1.0, -0.5]);
+            assert_relative_eq!(out[0], x, epsilon = 1e-6);
+            assert_relative_eq!(out[1], 0.25 * x, epsilon = 1e-6);
+        }
+    }
+
+    #[test]
+    pub fn auto_wah_follows_envelope() {
+        let mut wah = AutoWah::<[f64; 2]>::new(48000);
+        wah.set_sensitivity(0.0);
+        wah.set_range(400.0, 3200.0);
+        let w = 2.0 * std::f64::consts::PI * 1000.0 / 48000.0;
+
+        // Quiet playing barely opens it, loud playing reaches the top three octaves up.
+        for (amplitude, expected) in [(0.01, 400.0 * 8f64.powf(0.01)), (2.0, 3200.0)].iter() {
+            let mut highest = 0.0f64;
+            for n in 0..4800 {
+                let x = amplitude * (w * n as f64).sin();
+                wah.tick([x, -x]);
+                highest = highest.max(wah.frequency());
+            }
+            assert_relative_eq!(highest, *expected, max_relative = 0.01);
+        }
+
+        // And closes again in silence.
+        for _ in 0..48000 {
+            wah.tick([0.0, 0.0]);
+        }
+        assert_relative_eq!(wah.frequency(), 400.0, max_relative = 1e-3);
+
+        // A range given upside down still sweeps upwards.
+        wah.set_range(3200.0, 400.0);
+        for _ in 0..48000 {
+            wah.tick([0.0, 0.0]);
+        }
+        assert_relative_eq!(wah.frequency(), 400.0, max_relative = 1e-3);
+    }
+
+    #[test]
+    pub fn auto_wah_lfo_and_mix() {
+        let mut wah = AutoWah::<f64>::new(48000);
+        wah.set_range(500.0, 2000.0);
+        wah.set_lfo(true);
+        wah.set_lfo_rate(4.0);
+        wah.set_mix(0.0);
+
+        let mut lowest = f64::INFINITY;
+        let mut highest = 0.0f64;
+        for n in 0..48000 {
+            let x = (n as f64 * 0.01).sin();
+            assert_relative_eq!(wah.tick(x), x, epsilon = 1e-12);
+            lowest = lowest.min(wah.frequency());
+            highest = highest.max(wah.frequency());
+        }
+        assert_relative_eq!(lowest, 500.0, max_relative = 0.01);
+        assert_relative_eq!(highest, 2000.0, max_relative = 0.01);
+    }
+
+    #[test]
+    pub fn moving_source_doppler() {
+        // A source coming almost straight at the listener at a tenth of the speed of sound.
+        let sample_rate = 48000;
+        let speed = room::SPEED_OF_SOUND / 10.0;
+        let mut source = MovingSource::<f64>::new(
+            vec![
+                (0.0, [-1000.0, 5.0, 2.0]),
+                (10.0, [-1000.0 + 10.0 * speed, 5.0, 2.0]),
+            ],
+            [0.0, 0.0, 2.0],
+            sample_rate,
+        );
+        source.set_ground_reflection(0.0);
+
+        let frequency = 1000.0;
+        let w = 2.0 * std::f64::consts::PI * frequency / sample_rate as f64;
+        let out: Vec<f64> = (0..3 * sample_rate)
+            .map(|n| source.tick((w * n as f64).sin()))
+            .collect();
+
+        // Time the rising zero crossings in the third second, when the sound has arrived.
+        let measured = measure_frequency(&out, 2 * sample_rate, 3 * sample_rate, sample_rate);
+
+        assert_relative_eq!(measured, frequency / (1.0 - 0.1), max_relative = 1e-3);
+    }
 }
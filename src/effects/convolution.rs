@@ -0,0 +1,378 @@
+use std::io;
+
+use dasp::frame::Stereo;
+use dasp::Sample;
+
+use super::{sample_from_f64, sample_to_f64};
+use crate::delay_line::DelayLine;
+use crate::fft::{Complex, Fft};
+use crate::filter;
+use crate::wav::Wav;
+
+/// Longest pre-delay of the convolution reverb, in seconds.
+pub const CONVOLUTION_MAX_PRE_DELAY: f64 = 0.5;
+
+const CONVOLUTION_BLOCK_SIZE: usize = 256;
+
+/// Length of the fade applied to the end of a trimmed impulse response, in seconds.
+const TRIM_FADE: f64 = 0.01;
+
+/// Uniformly partitioned FFT convolution (overlap-save).
+///
+/// The impulse response is cut into blocks which are convolved in the frequency domain with the
+/// matching earlier input blocks, so the cost per sample grows only slowly with its length. The
+/// output lags the input by one block.
+pub struct Convolver {
+    block_size: usize,
+    fft: Fft,
+    partitions: Vec<Vec<Complex>>,
+    // Spectra of the most recent input blocks, as a ring with `newest` the latest one.
+    input_spectra: Vec<Vec<Complex>>,
+    newest: usize,
+    input: Vec<f64>,
+    output: Vec<f64>,
+    position: usize,
+    scratch: Vec<Complex>,
+}
+
+impl Convolver {
+    pub fn new(impulse_response: &[f64], block_size: usize) -> Self {
+        assert!(block_size.is_power_of_two());
+
+        let fft = Fft::new(2 * block_size);
+        let partitions: Vec<Vec<Complex>> = impulse_response
+            .chunks(block_size)
+            .map(|chunk| {
+                let mut spectrum = vec![Complex::default(); 2 * block_size];
+                for (s, h) in spectrum.iter_mut().zip(chunk.iter()) {
+                    s.re = *h;
+                }
+                fft.forward(&mut spectrum);
+                spectrum
+            })
+            .collect();
+        let partition_count = partitions.len().max(1);
+
+        Convolver {
+            block_size,
+            fft,
+            partitions,
+            input_spectra: vec![vec![Complex::default(); 2 * block_size]; partition_count],
+            newest: 0,
+            input: vec![0.0; 2 * block_size],
+            output: vec![0.0; block_size],
+            position: 0,
+            scratch: vec![Complex::default(); 2 * block_size],
+        }
+    }
+
+    /// Delay in samples between the input and the output.
+    pub fn latency(&self) -> usize {
+        self.block_size
+    }
+
+    pub fn tick(&mut self, input: f64) -> f64 {
+        let out = self.output[self.position];
+        self.input[self.block_size + self.position] = input;
+        self.position += 1;
+
+        if self.position == self.block_size {
+            self.process_block();
+            self.position = 0;
+        }
+
+        out
+    }
+
+    fn process_block(&mut self) {
+        let count = self.input_spectra.len();
+        self.newest = (self.newest + 1) % count;
+
+        let spectrum = &mut self.input_spectra[self.newest];
+        for (s, x) in spectrum.iter_mut().zip(self.input.iter()) {
+            *s = Complex::new(*x, 0.0);
+        }
+        self.fft.forward(spectrum);
+
+        for s in self.scratch.iter_mut() {
+            *s = Complex::default();
+        }
+        for (age, partition) in self.partitions.iter().enumerate() {
+            let spectrum = &self.input_spectra[(self.newest + count - age) % count];
+            for ((acc, x), h) in self
+                .scratch
+                .iter_mut()
+                .zip(spectrum.iter())
+                .zip(partition.iter())
+            {
+                *acc = *acc + *x * *h;
+            }
+        }
+        self.fft.inverse(&mut self.scratch);
+
+        // The first half is wrapped around by the circular convolution, the second is valid.
+        for (o, s) in self
+            .output
+            .iter_mut()
+            .zip(self.scratch[self.block_size..].iter())
+        {
+            *o = s.re;
+        }
+        self.input.copy_within(self.block_size.., 0);
+    }
+}
+
+/// Reverb that convolves with a recorded or synthesized impulse response.
+///
+/// Mono impulse responses are used for both channels, stereo ones one channel each, and true
+/// stereo ones have four channels in the order left to left, left to right, right to left and
+/// right to right.
+pub struct ConvolutionReverb<S> {
+    impulse_response: Vec<Vec<f64>>,
+    // (input channel, output channel, convolver)
+    convolvers: Vec<(usize, usize, Convolver)>,
+    pre_delay_line: DelayLine<Vec<Stereo<f64>>>,
+    pre_delay: usize,
+    wet: f64,
+    dry: f64,
+    sample_rate: usize,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> ConvolutionReverb<S> {
+    /// Fails with `InvalidData` unless the impulse response has 1, 2 or 4 channels. It is
+    /// resampled if its sample rate differs from `sample_rate`.
+    pub fn new(impulse_response: &Wav, sample_rate: usize) -> io::Result<Self> {
+        let channels = impulse_response.channels.len();
+        if !(channels == 1 || channels == 2 || channels == 4) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("impulse response has {} channels, not 1, 2 or 4", channels),
+            ));
+        }
+
+        let impulse_response = impulse_response
+            .channels
+            .iter()
+            .map(|c| {
+                if impulse_response.sample_rate == sample_rate {
+                    c.clone()
+                } else {
+                    filter::resample(c, impulse_response.sample_rate, sample_rate)
+                }
+            })
+            .collect();
+
+        let max_pre_delay = (CONVOLUTION_MAX_PRE_DELAY * sample_rate as f64) as usize;
+        let mut reverb = ConvolutionReverb {
+            impulse_response,
+            convolvers: vec![],
+            pre_delay_line: DelayLine::new(vec![[0.0; 2]; max_pre_delay + 2], 0),
+            pre_delay: 0,
+            wet: 1.0,
+            dry: 0.0,
+            sample_rate,
+            sample: std::marker::PhantomData,
+        };
+        reverb.set_trim(0.0, None);
+        Ok(reverb)
+    }
+
+    /// Delay in samples between the input and the start of the reverb, without pre-delay.
+    pub fn latency(&self) -> usize {
+        CONVOLUTION_BLOCK_SIZE
+    }
+
+    /// Uses only the part of the impulse response from `start` and `length` long, both in
+    /// seconds. The end of a shortened response is faded out to avoid a click.
+    pub fn set_trim(&mut self, start: f64, length: Option<f64>) {
+        let full_length = self.impulse_response[0].len();
+        let first = ((start * self.sample_rate as f64) as usize).min(full_length);
+        let last = match length {
+            Some(l) => (first + (l * self.sample_rate as f64) as usize).min(full_length),
+            None => full_length,
+        };
+        let fade = if last < full_length {
+            ((TRIM_FADE * self.sample_rate as f64) as usize).min(last - first)
+        } else {
+            0
+        };
+
+        let trimmed: Vec<Vec<f64>> = self
+            .impulse_response
+            .iter()
+            .map(|c| {
+                let mut part = c[first..last].to_vec();
+                let part_length = part.len();
+                for (i, v) in part.iter_mut().skip(part_length - fade).enumerate() {
+                    *v *= 1.0 - (i + 1) as f64 / fade as f64;
+                }
+                part
+            })
+            .collect();
+
+        let routing: &[(usize, usize, usize)] = match trimmed.len() {
+            1 => &[(0, 0, 0), (1, 1, 0)],
+            2 => &[(0, 0, 0), (1, 1, 1)],
+            _ => &[(0, 0, 0), (0, 1, 1), (1, 0, 2), (1, 1, 3)],
+        };
+        self.convolvers = routing
+            .iter()
+            .map(|(input, output, channel)| {
+                (
+                    *input,
+                    *output,
+                    Convolver::new(&trimmed[*channel], CONVOLUTION_BLOCK_SIZE),
+                )
+            })
+            .collect();
+    }
+
+    /// Delay before the reverb starts, in seconds up to `CONVOLUTION_MAX_PRE_DELAY`.
+    pub fn set_pre_delay(&mut self, pre_delay: f64) {
+        let pre_delay = (pre_delay * self.sample_rate as f64).round() as usize;
+        self.pre_delay = pre_delay.min(self.pre_delay_line.capacity() - 1);
+    }
+
+    pub fn set_wet(&mut self, wet: f64) {
+        self.wet = wet.max(0.0);
+    }
+
+    pub fn set_dry(&mut self, dry: f64) {
+        self.dry = dry.max(0.0);
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let dry = [sample_to_f64(in_frame[0]), sample_to_f64(in_frame[1])];
+
+        self.pre_delay_line.tick(dry);
+        let delayed = self.pre_delay_line.tap(self.pre_delay);
+
+        let mut wet = [0.0; 2];
+        for (input, output, convolver) in self.convolvers.iter_mut() {
+            wet[*output] += convolver.tick(delayed[*input]);
+        }
+
+        [
+            sample_from_f64(dry[0] * self.dry + wet[0] * self.wet),
+            sample_from_f64(dry[1] * self.dry + wet[1] * self.wet),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn convolver_matches_direct_convolution() {
+        let impulse_response: Vec<f64> = (0..1000)
+            .map(|n| (n as f64 * 0.37).sin() * (-(n as f64) / 300.0).exp())
+            .collect();
+        let input: Vec<f64> = (0..3000).map(|n| (n as f64 * 0.11).cos()).collect();
+
+        let mut convolver = Convolver::new(&impulse_response, 64);
+        let latency = convolver.latency();
+        let output: Vec<f64> = input.iter().map(|x| convolver.tick(*x)).collect();
+
+        for (m, out) in output.iter().skip(latency).enumerate() {
+            let expected: f64 = (0..=m.min(impulse_response.len() - 1))
+                .map(|k| impulse_response[k] * input[m - k])
+                .sum();
+            assert_relative_eq!(*out, expected, epsilon = 1e-9);
+        }
+    }
+
+    fn impulse_response(channels: Vec<Vec<f64>>, sample_rate: usize) -> Wav {
+        Wav {
+            sample_rate,
+            channels,
+        }
+    }
+
+    #[test]
+    pub fn pre_delay_and_latency() {
+        let mut ir = vec![0.0; 20];
+        ir[0] = 1.0;
+        ir[10] = 0.5;
+        let mut reverb =
+            ConvolutionReverb::<f64>::new(&impulse_response(vec![ir], 1000), 1000).unwrap();
+        reverb.set_pre_delay(0.03);
+        let offset = reverb.latency() + 30;
+
+        let output: Vec<[f64; 2]> = (0..400)
+            .map(|n| reverb.tick(if n == 0 { [1.0, -1.0] } else { [0.0, 0.0] }))
+            .collect();
+
+        for (n, o) in output.iter().enumerate() {
+            let expected = if n == offset {
+                1.0
+            } else if n == offset + 10 {
+                0.5
+            } else {
+                0.0
+            };
+            assert_relative_eq!(o[0], expected, epsilon = 1e-9);
+            assert_relative_eq!(o[1], -expected, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn true_stereo_routing() {
+        // Only left to right is connected.
+        let mut impulse = vec![0.0; 8];
+        impulse[0] = 1.0;
+        let silent = vec![0.0; 8];
+        let channels = vec![silent.clone(), impulse, silent.clone(), silent];
+        let mut reverb =
+            ConvolutionReverb::<f32>::new(&impulse_response(channels, 1000), 1000).unwrap();
+        let latency = reverb.latency();
+
+        let output: Vec<[f32; 2]> = (0..400)
+            .map(|n| reverb.tick(if n == 0 { [1.0, 1.0] } else { [0.0, 0.0] }))
+            .collect();
+
+        assert_relative_eq!(output[latency][1], 1.0, epsilon = 1e-6);
+        assert!(output.iter().all(|o| o[0].abs() < 1e-6));
+    }
+
+    #[test]
+    pub fn trimming() {
+        let mut ir = vec![0.0; 200];
+        ir[0] = 1.0;
+        ir[100] = 0.5;
+        let mut reverb =
+            ConvolutionReverb::<f64>::new(&impulse_response(vec![ir], 1000), 1000).unwrap();
+        reverb.set_trim(0.05, Some(0.1));
+        let latency = reverb.latency();
+
+        let output: Vec<[f64; 2]> = (0..600)
+            .map(|n| reverb.tick(if n == 0 { [1.0, 1.0] } else { [0.0, 0.0] }))
+            .collect();
+
+        for (n, o) in output.iter().enumerate() {
+            let expected = if n == latency + 50 { 0.5 } else { 0.0 };
+            assert_relative_eq!(o[0], expected, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn resamples_impulse_response() {
+        let ir: Vec<f64> = (0..4410).map(|n| (n as f64 * 0.01).sin()).collect();
+        let reverb =
+            ConvolutionReverb::<f32>::new(&impulse_response(vec![ir], 44100), 48000).unwrap();
+
+        assert_eq!(reverb.impulse_response[0].len(), 4800);
+    }
+
+    #[test]
+    pub fn rejects_unsupported_channel_counts() {
+        let channels = vec![vec![1.0; 8]; 3];
+        let error = ConvolutionReverb::<f32>::new(&impulse_response(channels, 1000), 1000)
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(ConvolutionReverb::<f32>::new(&impulse_response(vec![], 1000), 1000).is_err());
+    }
+}
@@ -0,0 +1,281 @@
+use dasp::Frame;
+use dasp::Sample;
+
+use super::{sample_from_f64, sample_to_f64};
+use crate::filter::{Biquad, BiquadCoefficients, Oversampler};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DistortionCurve {
+    Tanh,
+    /// Cubic soft clipping, flat from an input of 1.
+    SoftClip,
+    HardClip,
+    /// Biased tanh that clips the positive half earlier, which adds even harmonics.
+    Tube,
+    /// Folds everything beyond 1 back down again.
+    Foldback,
+}
+
+/// How far the tube curve is biased.
+const TUBE_BIAS: f64 = 0.3;
+
+impl DistortionCurve {
+    pub fn shape(self, x: f64) -> f64 {
+        match self {
+            DistortionCurve::Tanh => x.tanh(),
+            DistortionCurve::SoftClip => {
+                if x.abs() < 1.0 {
+                    1.5 * x - 0.5 * x * x * x
+                } else {
+                    x.signum()
+                }
+            }
+            DistortionCurve::HardClip => x.clamp(-1.0, 1.0),
+            DistortionCurve::Tube => (x + TUBE_BIAS).tanh() - TUBE_BIAS.tanh(),
+            DistortionCurve::Foldback => {
+                let t = (x + 1.0).rem_euclid(4.0);
+                if t < 2.0 {
+                    t - 1.0
+                } else {
+                    3.0 - t
+                }
+            }
+        }
+    }
+}
+
+/// Waveshaping distortion.
+///
+/// The input is turned up by the pre gain, optionally highpassed to keep the lows from muddying
+/// the distortion, shaped at the oversampled rate, lowpassed by the tone filter, freed of the DC
+/// the asymmetric curves add and turned back down by the post gain.
+pub struct Distortion<T> {
+    curve: DistortionCurve,
+    pre_gain: f64,
+    post_gain: f64,
+    pre_highpass: Option<Biquad<T>>,
+    tone: Option<Biquad<T>>,
+    dc_blocker: Biquad<T>,
+    // One for each channel, unless the factor is 1.
+    oversamplers: Vec<Oversampler>,
+    sample_rate: usize,
+}
+
+impl<T: Frame> Distortion<T> {
+    pub fn new(curve: DistortionCurve, sample_rate: usize) -> Self {
+        Distortion {
+            curve,
+            pre_gain: 1.0,
+            post_gain: 1.0,
+            pre_highpass: None,
+            tone: None,
+            dc_blocker: Biquad::new(BiquadCoefficients::highpass(
+                10.0,
+                std::f64::consts::FRAC_1_SQRT_2,
+                sample_rate,
+            )),
+            oversamplers: vec![],
+            sample_rate,
+        }
+    }
+
+    /// Delay in samples caused by the oversampling.
+    pub fn latency(&self) -> usize {
+        self.oversamplers.first().map_or(0, |o| o.latency())
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        let mut x = in_frame.scale_amp(self.pre_gain.to_sample());
+        if let Some(highpass) = self.pre_highpass.as_mut() {
+            x = highpass.tick(x);
+        }
+
+        let curve = self.curve;
+        let oversamplers = &mut self.oversamplers;
+        let mut shaped = T::from_fn(|channel| {
+            let input = sample_to_f64(*x.channel(channel).unwrap());
+            let output = match oversamplers.get_mut(channel) {
+                None => curve.shape(input),
+                Some(oversampler) => {
+                    let mut up = [0.0; 8];
+                    let up = &mut up[..oversampler.factor()];
+                    up.copy_from_slice(oversampler.upsample(input));
+                    for u in up.iter_mut() {
+                        *u = curve.shape(*u);
+                    }
+                    oversampler.downsample(up)
+                }
+            };
+            sample_from_f64(output)
+        });
+
+        if let Some(tone) = self.tone.as_mut() {
+            shaped = tone.tick(shaped);
+        }
+        self.dc_blocker
+            .tick(shaped)
+            .scale_amp(self.post_gain.to_sample())
+    }
+
+    pub fn set_curve(&mut self, curve: DistortionCurve) {
+        self.curve = curve;
+    }
+
+    /// In dB.
+    pub fn set_pre_gain(&mut self, gain: f64) {
+        self.pre_gain = 10f64.powf(gain / 20.0);
+    }
+
+    /// In dB.
+    pub fn set_post_gain(&mut self, gain: f64) {
+        self.post_gain = 10f64.powf(gain / 20.0);
+    }
+
+    /// Highpass before the distortion in Hz, `None` turns it off.
+    pub fn set_pre_highpass(&mut self, frequency: Option<f64>) {
+        self.pre_highpass = frequency.map(|f| {
+            Biquad::new(BiquadCoefficients::highpass(
+                f,
+                std::f64::consts::FRAC_1_SQRT_2,
+                self.sample_rate,
+            ))
+        });
+    }
+
+    /// Lowpass after the distortion in Hz, `None` turns it off.
+    pub fn set_tone(&mut self, frequency: Option<f64>) {
+        self.tone = frequency.map(|f| {
+            Biquad::new(BiquadCoefficients::lowpass(
+                f,
+                std::f64::consts::FRAC_1_SQRT_2,
+                self.sample_rate,
+            ))
+        });
+    }
+
+    /// Runs the curve at 1, 2, 4 or 8 times the sample rate.
+    pub fn set_oversampling(&mut self, factor: usize) {
+        assert!(factor == 1 || factor == 2 || factor == 4 || factor == 8);
+
+        self.oversamplers = if factor == 1 {
+            vec![]
+        } else {
+            (0..T::CHANNELS).map(|_| Oversampler::new(factor)).collect()
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fft::{Complex, Fft};
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn curves() {
+        for curve in [
+            DistortionCurve::Tanh,
+            DistortionCurve::SoftClip,
+            DistortionCurve::HardClip,
+            DistortionCurve::Tube,
+            DistortionCurve::Foldback,
+        ]
+        .iter()
+        {
+            assert_relative_eq!(curve.shape(0.0), 0.0, epsilon = 1e-12);
+            for x in [0.1, 0.5, 2.0, 10.0].iter() {
+                assert!(curve.shape(*x).abs() <= 1.0);
+            }
+        }
+
+        assert_relative_eq!(DistortionCurve::HardClip.shape(0.7), 0.7);
+        assert_relative_eq!(DistortionCurve::HardClip.shape(-3.0), -1.0);
+        assert_relative_eq!(DistortionCurve::SoftClip.shape(1.0), 1.0);
+        assert_relative_eq!(DistortionCurve::SoftClip.shape(-0.5), -0.6875);
+        assert_relative_eq!(DistortionCurve::Foldback.shape(0.5), 0.5);
+        assert_relative_eq!(DistortionCurve::Foldback.shape(1.5), 0.5);
+        assert_relative_eq!(DistortionCurve::Foldback.shape(-2.5), 0.5);
+        assert_relative_eq!(DistortionCurve::Foldback.shape(4.5), 0.5);
+
+        // The tube curve gives way sooner going up than going down.
+        let tube = DistortionCurve::Tube;
+        assert!(tube.shape(2.0) < -tube.shape(-2.0));
+    }
+
+    /// Fraction in dB of the output power of a driven sine that is not at its harmonics.
+    fn aliasing(factor: usize) -> f64 {
+        let size = 4096;
+        let sample_rate = 48000;
+        // Exactly on a bin, so the harmonics that do not alias land on bins too.
+        let bin = 427;
+
+        let mut distortion = Distortion::<f64>::new(DistortionCurve::HardClip, sample_rate);
+        distortion.set_pre_gain(6.0);
+        distortion.set_oversampling(factor);
+
+        let w = 2.0 * std::f64::consts::PI * bin as f64 / size as f64;
+        let out: Vec<f64> = (0..4 * size)
+            .map(|n| distortion.tick((w * n as f64).sin()))
+            .collect();
+
+        let mut spectrum: Vec<Complex> = out[3 * size..]
+            .iter()
+            .map(|x| Complex::new(*x, 0.0))
+            .collect();
+        Fft::new(size).forward(&mut spectrum);
+
+        let power = |k: usize| spectrum[k].re * spectrum[k].re + spectrum[k].im * spectrum[k].im;
+        let total: f64 = (1..size / 2).map(power).sum();
+        let harmonics: f64 = (1..)
+            .map(|h| h * bin)
+            .take_while(|k| *k < size / 2)
+            .map(power)
+            .sum();
+        10.0 * ((total - harmonics) / total).log10()
+    }
+
+    #[test]
+    pub fn oversampling_reduces_aliasing() {
+        let none = aliasing(1);
+        let two = aliasing(2);
+        let four = aliasing(4);
+        let eight = aliasing(8);
+
+        assert!(none > -30.0);
+        assert!(two < none - 10.0);
+        assert!(four < two - 6.0);
+        assert!(eight < four - 6.0);
+        assert!(eight < -60.0);
+    }
+
+    #[test]
+    pub fn gains_and_latency() {
+        // A quiet signal is left alone by the curve, so the oversampled distortion only delays
+        // what the plain one does.
+        let distortion = |factor: usize| {
+            let mut distortion = Distortion::<[f64; 2]>::new(DistortionCurve::Tanh, 48000);
+            distortion.set_pre_gain(-40.0);
+            distortion.set_post_gain(40.0);
+            distortion.set_oversampling(factor);
+            distortion
+        };
+        let mut plain = distortion(1);
+        let mut oversampled = distortion(4);
+        let latency = oversampled.latency();
+
+        let w = 2.0 * std::f64::consts::PI * 500.0 / 48000.0;
+        let mut reference = vec![];
+        for n in 0..2000 {
+            let x = (w * n as f64).sin();
+            reference.push(plain.tick([x, -x]));
+            let out = oversampled.tick([x, -x]);
+            if n >= 2 * latency {
+                let expected = reference[n - latency];
+                assert_relative_eq!(out[0], expected[0], epsilon = 2e-3);
+                assert_relative_eq!(out[1], expected[1], epsilon = 2e-3);
+            }
+        }
+        // The gains cancel.
+        assert_relative_eq!(reference[1999][0], (w * 1999.0).sin(), epsilon = 0.03);
+    }
+}
@@ -0,0 +1,781 @@
+//! Dynamics processing: envelope following, compression, limiting and gating.
+
+use std::collections::VecDeque;
+
+use dasp::frame::Stereo;
+use dasp::Sample;
+
+use super::{sample_from_f64, sample_to_f64};
+use crate::delay_line::DelayLine;
+use crate::filter::{Biquad, BiquadCoefficients};
+
+/// Longest lookahead of the limiter, in seconds.
+pub const LIMITER_MAX_LOOKAHEAD: f64 = 0.02;
+
+fn to_db(amplitude: f64) -> f64 {
+    20.0 * amplitude.max(1e-10).log10()
+}
+
+fn from_db(db: f64) -> f64 {
+    10f64.powf(db / 20.0)
+}
+
+/// One pole smoothing coefficient for a time constant in seconds. 0 follows instantly.
+fn time_coefficient(time: f64, sample_rate: f64) -> f64 {
+    if time > 0.0 {
+        (-1.0 / (time * sample_rate)).exp()
+    } else {
+        0.0
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Detection {
+    Peak,
+    Rms,
+}
+
+/// Follows the level of a signal, rising with the attack and falling with the release time
+/// constant.
+pub struct EnvelopeFollower {
+    detection: Detection,
+    attack: f64,
+    release: f64,
+    // The rectified or squared level, depending on the detection.
+    state: f64,
+    sample_rate: f64,
+}
+
+impl EnvelopeFollower {
+    /// `attack` and `release` in seconds.
+    pub fn new(detection: Detection, attack: f64, release: f64, sample_rate: usize) -> Self {
+        let sample_rate = sample_rate as f64;
+        EnvelopeFollower {
+            detection,
+            attack: time_coefficient(attack, sample_rate),
+            release: time_coefficient(release, sample_rate),
+            state: 0.0,
+            sample_rate,
+        }
+    }
+
+    pub fn set_detection(&mut self, detection: Detection) {
+        if detection != self.detection {
+            self.state = match detection {
+                Detection::Peak => self.state.sqrt(),
+                Detection::Rms => self.state * self.state,
+            };
+        }
+        self.detection = detection;
+    }
+
+    pub fn set_attack(&mut self, attack: f64) {
+        self.attack = time_coefficient(attack, self.sample_rate);
+    }
+
+    pub fn set_release(&mut self, release: f64) {
+        self.release = time_coefficient(release, self.sample_rate);
+    }
+
+    /// The current level as an amplitude.
+    pub fn value(&self) -> f64 {
+        match self.detection {
+            Detection::Peak => self.state,
+            Detection::Rms => self.state.sqrt(),
+        }
+    }
+
+    pub fn tick(&mut self, input: f64) -> f64 {
+        let level = match self.detection {
+            Detection::Peak => input.abs(),
+            Detection::Rms => input * input,
+        };
+        let coefficient = if level > self.state {
+            self.attack
+        } else {
+            self.release
+        };
+        self.state = level + (self.state - level) * coefficient;
+        self.value()
+    }
+}
+
+/// Feed-forward compressor with a soft knee.
+///
+/// Each channel has its own detector. The stereo link moves the level each channel is compressed
+/// by from its own over to the louder of the two, which keeps the stereo image from shifting.
+pub struct Compressor<S> {
+    followers: [EnvelopeFollower; 2],
+    threshold: f64,
+    ratio: f64,
+    knee: f64,
+    makeup: f64,
+    link: f64,
+    gain_reduction: [f64; 2],
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> Compressor<S> {
+    pub fn new(sample_rate: usize) -> Self {
+        let follower = || EnvelopeFollower::new(Detection::Peak, 0.01, 0.1, sample_rate);
+        Compressor {
+            followers: [follower(), follower()],
+            threshold: -20.0,
+            ratio: 4.0,
+            knee: 6.0,
+            makeup: 0.0,
+            link: 1.0,
+            gain_reduction: [0.0; 2],
+            sample: std::marker::PhantomData,
+        }
+    }
+
+    /// Output level in dB for a detected level in dB, before makeup gain.
+    pub fn static_curve(&self, level: f64) -> f64 {
+        let over = level - self.threshold;
+        let slope = 1.0 / self.ratio - 1.0;
+
+        if 2.0 * over <= -self.knee {
+            level
+        } else if 2.0 * over < self.knee {
+            let into_knee = over + self.knee / 2.0;
+            level + slope * into_knee * into_knee / (2.0 * self.knee)
+        } else {
+            level + slope * over
+        }
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        self.tick_with_sidechain(in_frame, in_frame)
+    }
+
+    /// Compresses `in_frame` by the level of `sidechain`.
+    pub fn tick_with_sidechain(&mut self, in_frame: Stereo<S>, sidechain: Stereo<S>) -> Stereo<S> {
+        let levels = [
+            self.followers[0].tick(sample_to_f64(sidechain[0])),
+            self.followers[1].tick(sample_to_f64(sidechain[1])),
+        ];
+        let loudest = levels[0].max(levels[1]);
+
+        let mut out = in_frame;
+        for (channel, level) in levels.iter().enumerate() {
+            let level = to_db(self.link * loudest + (1.0 - self.link) * level);
+            self.gain_reduction[channel] = level - self.static_curve(level);
+            let gain = from_db(self.makeup - self.gain_reduction[channel]);
+            out[channel] = sample_from_f64(sample_to_f64(in_frame[channel]) * gain);
+        }
+        out
+    }
+
+    /// The current gain reduction of each channel in dB, not counting makeup gain.
+    pub fn gain_reduction(&self) -> [f64; 2] {
+        self.gain_reduction
+    }
+
+    /// In dB.
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = threshold;
+    }
+
+    /// How many dB the input has to rise above the threshold for the output to rise 1 dB.
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.ratio = ratio.max(1.0);
+    }
+
+    /// Width in dB of the transition around the threshold, 0 for a hard knee.
+    pub fn set_knee(&mut self, knee: f64) {
+        self.knee = knee.max(0.0);
+    }
+
+    /// In dB.
+    pub fn set_makeup(&mut self, makeup: f64) {
+        self.makeup = makeup;
+    }
+
+    /// In seconds.
+    pub fn set_attack(&mut self, attack: f64) {
+        for follower in self.followers.iter_mut() {
+            follower.set_attack(attack);
+        }
+    }
+
+    /// In seconds.
+    pub fn set_release(&mut self, release: f64) {
+        for follower in self.followers.iter_mut() {
+            follower.set_release(release);
+        }
+    }
+
+    pub fn set_detection(&mut self, detection: Detection) {
+        for follower in self.followers.iter_mut() {
+            follower.set_detection(detection);
+        }
+    }
+
+    /// Between 0 for independent channels and 1 for fully linked.
+    pub fn set_stereo_link(&mut self, link: f64) {
+        self.link = link.clamp(0.0, 1.0);
+    }
+}
+
+/// Brickwall limiter that never lets a sample past the ceiling.
+///
+/// The audio is delayed by the lookahead while the gain needed for each peak is held for the
+/// lookahead and averaged over it, so the gain ramps down just in time for the peak. The gain
+/// then recovers with the release time constant.
+pub struct Limiter<S> {
+    delay_line: DelayLine<Vec<Stereo<f64>>>,
+    // (frame, required gain) of the lowest gains in the lookahead window, increasing.
+    minimum: VecDeque<(usize, f64)>,
+    // The held gains being averaged, and their sum.
+    average: VecDeque<f64>,
+    average_sum: f64,
+    gain: f64,
+    frame: usize,
+    lookahead: usize,
+    ceiling: f64,
+    release: f64,
+    sample_rate: f64,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> Limiter<S> {
+    pub fn new(sample_rate: usize) -> Self {
+        let capacity = (LIMITER_MAX_LOOKAHEAD * sample_rate as f64).ceil() as usize + 2;
+        let mut limiter = Limiter {
+            delay_line: DelayLine::new(vec![[0.0; 2]; capacity], 0),
+            // Both hold at most one more than the lookahead, which is less than the capacity.
+            minimum: VecDeque::with_capacity(capacity),
+            average: VecDeque::with_capacity(capacity),
+            average_sum: 0.0,
+            gain: 1.0,
+            frame: 0,
+            lookahead: 1,
+            ceiling: 1.0,
+            release: 0.0,
+            sample_rate: sample_rate as f64,
+            sample: std::marker::PhantomData,
+        };
+        limiter.set_lookahead(0.005);
+        limiter.set_release(0.05);
+        limiter
+    }
+
+    /// Delay in samples between the input and the output.
+    pub fn latency(&self) -> usize {
+        self.lookahead - 1
+    }
+
+    /// Lookahead in seconds, up to `LIMITER_MAX_LOOKAHEAD`. Changing it resets the limiter.
+    pub fn set_lookahead(&mut self, lookahead: f64) {
+        let capacity = self.delay_line.capacity();
+        self.lookahead = ((lookahead * self.sample_rate).round() as usize).clamp(1, capacity - 1);
+
+        for _ in 0..capacity {
+            self.delay_line.tick([0.0; 2]);
+        }
+        self.minimum.clear();
+        self.average.clear();
+        self.average.resize(self.lookahead, 1.0);
+        self.average_sum = self.lookahead as f64;
+        self.gain = 1.0;
+    }
+
+    /// In dB.
+    pub fn set_ceiling(&mut self, ceiling: f64) {
+        self.ceiling = from_db(ceiling);
+    }
+
+    /// In seconds.
+    pub fn set_release(&mut self, release: f64) {
+        self.release = time_coefficient(release, self.sample_rate);
+    }
+
+    /// The current gain reduction in dB.
+    pub fn gain_reduction(&self) -> f64 {
+        -to_db(self.gain)
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let input = [sample_to_f64(in_frame[0]), sample_to_f64(in_frame[1])];
+        self.delay_line.tick(input);
+
+        let peak = input[0].abs().max(input[1].abs());
+        let required = if peak > self.ceiling {
+            self.ceiling / peak
+        } else {
+            1.0
+        };
+
+        // Sliding minimum over the lookahead window.
+        while self.minimum.back().is_some_and(|(_, g)| *g >= required) {
+            self.minimum.pop_back();
+        }
+        self.minimum.push_back((self.frame, required));
+        while self.minimum[0].0 + self.lookahead <= self.frame {
+            self.minimum.pop_front();
+        }
+        let held = self.minimum[0].1;
+        self.frame += 1;
+
+        // Moving average of the held gain.
+        self.average.push_back(held);
+        self.average_sum += held - self.average.pop_front().unwrap_or(1.0);
+        let target = (self.average_sum / self.lookahead as f64).min(1.0);
+
+        self.gain = if target < self.gain {
+            target
+        } else {
+            target + (self.gain - target) * self.release
+        };
+
+        let out = self.delay_line.tap(self.latency());
+        [
+            sample_from_f64(out[0] * self.gain),
+            sample_from_f64(out[1] * self.gain),
+        ]
+    }
+}
+
+/// Noise gate and downward expander.
+///
+/// The gate opens when the detected level rises above the threshold and closes once it has been
+/// below the threshold minus the hysteresis for the hold time. While closed the signal is turned
+/// down by the ratio for every dB it is under the threshold, but no further than the range. The
+/// default infinite ratio makes it a gate, lower ratios an expander. The detector sees the louder
+/// channel, optionally after filtering each channel with a highpass and lowpass filter.
+pub struct Gate<S> {
+    follower: EnvelopeFollower,
+    highpass: Option<Biquad<[f64; 2]>>,
+    lowpass: Option<Biquad<[f64; 2]>>,
+    threshold: f64,
+    range: f64,
+    ratio: f64,
+    hysteresis: f64,
+    attack: f64,
+    release: f64,
+    hold: usize,
+    hold_left: usize,
+    open: bool,
+    gain: f64,
+    sample_rate: f64,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> Gate<S> {
+    pub fn new(sample_rate: usize) -> Self {
+        let mut gate = Gate {
+            follower: EnvelopeFollower::new(Detection::Peak, 0.0, 0.01, sample_rate),
+            highpass: None,
+            lowpass: None,
+            threshold: -40.0,
+            range: -80.0,
+            ratio: f64::INFINITY,
+            hysteresis: 3.0,
+            attack: 0.0,
+            release: 0.0,
+            hold: 0,
+            hold_left: 0,
+            open: false,
+            gain: 0.0,
+            sample_rate: sample_rate as f64,
+            sample: std::marker::PhantomData,
+        };
+        gate.gain = from_db(gate.range);
+        gate.set_attack(0.001);
+        gate.set_hold(0.02);
+        gate.set_release(0.1);
+        gate
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        self.tick_with_sidechain(in_frame, in_frame)
+    }
+
+    /// Gates `in_frame` by the level of `sidechain`.
+    pub fn tick_with_sidechain(&mut self, in_frame: Stereo<S>, sidechain: Stereo<S>) -> Stereo<S> {
+        // Filter before rectifying, rectified highs have plenty of lows of their own.
+        let mut key = [sample_to_f64(sidechain[0]), sample_to_f64(sidechain[1])];
+        if let Some(highpass) = self.highpass.as_mut() {
+            key = highpass.tick(key);
+        }
+        if let Some(lowpass) = self.lowpass.as_mut() {
+            key = lowpass.tick(key);
+        }
+        let detected = key[0].abs().max(key[1].abs());
+        let level = to_db(self.follower.tick(detected));
+
+        if level > self.threshold {
+            self.open = true;
+            self.hold_left = self.hold;
+        } else if level < self.threshold - self.hysteresis {
+            if self.hold_left > 0 {
+                self.hold_left -= 1;
+            } else {
+                self.open = false;
+            }
+        }
+
+        let target = if self.open {
+            1.0
+        } else {
+            from_db(((level - self.threshold) * (self.ratio - 1.0)).max(self.range))
+        };
+        let coefficient = if target > self.gain {
+            self.attack
+        } else {
+            self.release
+        };
+        self.gain = target + (self.gain - target) * coefficient;
+
+        [
+            sample_from_f64(sample_to_f64(in_frame[0]) * self.gain),
+            sample_from_f64(sample_to_f64(in_frame[1]) * self.gain),
+        ]
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// The current gain in dB.
+    pub fn gain(&self) -> f64 {
+        to_db(self.gain)
+    }
+
+    /// In dB.
+    pub fn set_threshold(&mut self, threshold: f64) {
+        self.threshold = threshold;
+    }
+
+    /// The most the signal is turned down while closed, in dB below 0.
+    pub fn set_range(&mut self, range: f64) {
+        self.range = range.min(0.0);
+    }
+
+    /// dB of attenuation per dB under the threshold plus one, so 2 doubles the distance to the
+    /// threshold. Infinity gates.
+    pub fn set_ratio(&mut self, ratio: f64) {
+        self.ratio = ratio.max(1.0);
+    }
+
+    /// How far in dB under the threshold the level has to fall before the gate closes.
+    pub fn set_hysteresis(&mut self, hysteresis: f64) {
+        self.hysteresis = hysteresis.max(0.0);
+    }
+
+    /// Time constant for opening, in seconds.
+    pub fn set_attack(&mut self, attack: f64) {
+        self.attack = time_coefficient(attack, self.sample_rate);
+    }
+
+    /// How long the gate stays open after the level falls under the threshold, in seconds.
+    pub fn set_hold(&mut self, hold: f64) {
+        self.hold = (hold.max(0.0) * self.sample_rate).round() as usize;
+    }
+
+    /// Time constant for closing, in seconds.
+    pub fn set_release(&mut self, release: f64) {
+        self.release = time_coefficient(release, self.sample_rate);
+    }
+
+    /// Filters the detector signal, so for example rumble or cymbal spill does not open the gate.
+    /// `None` turns a filter off.
+    pub fn set_sidechain_filter(&mut self, highpass: Option<f64>, lowpass: Option<f64>) {
+        let sample_rate = self.sample_rate as usize;
+        let q = std::f64::consts::FRAC_1_SQRT_2;
+        self.highpass =
+            highpass.map(|f| Biquad::new(BiquadCoefficients::highpass(f, q, sample_rate)));
+        self.lowpass = lowpass.map(|f| Biquad::new(BiquadCoefficients::lowpass(f, q, sample_rate)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn follower_attack_and_release() {
+        let mut follower = EnvelopeFollower::new(Detection::Peak, 0.01, 0.1, 1000);
+
+        let rising: Vec<f64> = (0..100).map(|_| follower.tick(-1.0)).collect();
+        assert_relative_eq!(rising[9], 1.0 - (-1.0f64).exp(), epsilon = 1e-9);
+
+        let falling: Vec<f64> = (0..100).map(|_| follower.tick(0.0)).collect();
+        assert_relative_eq!(falling[99], rising[99] * (-1.0f64).exp(), epsilon = 1e-9);
+    }
+
+    #[test]
+    pub fn follower_rms_of_sine() {
+        let mut follower = EnvelopeFollower::new(Detection::Rms, 0.5, 0.5, 48000);
+        let w = 2.0 * std::f64::consts::PI * 1000.0 / 48000.0;
+        let mut level = 0.0;
+        for n in 0..5 * 48000 {
+            level = follower.tick((w * n as f64).sin());
+        }
+        assert_relative_eq!(level, std::f64::consts::FRAC_1_SQRT_2, epsilon = 1e-3);
+    }
+
+    #[test]
+    pub fn compressor_static_curve() {
+        let mut compressor = Compressor::<f64>::new(48000);
+        compressor.set_threshold(-20.0);
+        compressor.set_ratio(4.0);
+        compressor.set_knee(10.0);
+
+        assert_relative_eq!(compressor.static_curve(-40.0), -40.0);
+        assert_relative_eq!(compressor.static_curve(-25.0), -25.0);
+        assert_relative_eq!(compressor.static_curve(0.0), -15.0);
+        // Halfway through the knee, an eighth of the knee width times the slope.
+        assert_relative_eq!(compressor.static_curve(-20.0), -20.0 - 0.75 * 10.0 / 8.0);
+
+        compressor.set_knee(0.0);
+        assert_relative_eq!(compressor.static_curve(-20.0), -20.0);
+        assert_relative_eq!(compressor.static_curve(-12.0), -18.0);
+    }
+
+    #[test]
+    pub fn compressor_settles_on_curve() {
+        let mut compressor = Compressor::<f64>::new(1000);
+        compressor.set_knee(0.0);
+        compressor.set_makeup(3.0);
+
+        let mut out = [0.0; 2];
+        for _ in 0..2000 {
+            out = compressor.tick([0.5, 0.5]);
+        }
+        // 0.5 is about 6 dB, which is 14 dB over the threshold and comes out 3.5 dB over it.
+        let expected = -20.0 + (to_db(0.5) + 20.0) / 4.0 + 3.0;
+        assert_relative_eq!(to_db(out[0]), expected, epsilon = 1e-6);
+        assert_relative_eq!(to_db(out[1]), expected, epsilon = 1e-6);
+    }
+
+    #[test]
+    pub fn compressor_stereo_link() {
+        let run = |link: f64| {
+            let mut compressor = Compressor::<f64>::new(1000);
+            compressor.set_stereo_link(link);
+            for _ in 0..2000 {
+                compressor.tick([0.5, 0.01]);
+            }
+            compressor.gain_reduction()
+        };
+
+        let linked = run(1.0);
+        assert!(linked[0] > 5.0);
+        assert_relative_eq!(linked[0], linked[1]);
+
+        let unlinked = run(0.0);
+        assert_relative_eq!(unlinked[0], linked[0]);
+        assert_relative_eq!(unlinked[1], 0.0);
+    }
+
+    #[test]
+    pub fn compressor_sidechain() {
+        let mut compressor = Compressor::<f32>::new(1000);
+        compressor.set_knee(0.0);
+        let mut out = [0.0; 2];
+        for _ in 0..2000 {
+            out = compressor.tick_with_sidechain([0.01, 0.01], [1.0, 1.0]);
+        }
+        // The 20 dB the sidechain is over the threshold are brought down to 5.
+        assert_relative_eq!(out[0], 0.01 * from_db(-15.0) as f32, epsilon = 1e-6);
+    }
+
+    #[test]
+    pub fn limiter_holds_ceiling() {
+        let sample_rate = 48000;
+        let mut limiter = Limiter::<f64>::new(sample_rate);
+        limiter.set_ceiling(-1.0);
+        let ceiling = from_db(-1.0);
+
+        // Bursts of a loud sine between quiet passages, with a few single sample spikes.
+        let w = 2.0 * std::f64::consts::PI * 440.0 / sample_rate as f64;
+        let input: Vec<f64> = (0..sample_rate)
+            .map(|n| {
+                let level = if (n / 4000) % 2 == 1 { 4.0 } else { 0.1 };
+                let spike = if n % 7919 == 0 { 8.0 } else { 0.0 };
+                level * (w * n as f64).sin() + spike
+            })
+            .collect();
+
+        let latency = limiter.latency();
+        for (n, x) in input.iter().enumerate() {
+            let out = limiter.tick([*x, -*x]);
+            assert!(out[0].abs() <= ceiling + 1e-12);
+            assert!(out[1].abs() <= ceiling + 1e-12);
+            if n >= latency {
+                // Only the gain is changed.
+                assert_relative_eq!(out[0], -out[1]);
+                let dry = input[n - latency];
+                assert!(out[0].abs() <= dry.abs() + 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    pub fn limiter_passes_quiet_signal() {
+        let mut limiter = Limiter::<f64>::new(1000);
+        limiter.set_lookahead(0.01);
+        let latency = limiter.latency();
+        assert_eq!(latency, 9);
+
+        for n in 0..100 {
+            let x = (n as f64 * 0.1).sin() * 0.5;
+            let out = limiter.tick([x, x]);
+            let expected = if n >= latency {
+                ((n - latency) as f64 * 0.1).sin() * 0.5
+            } else {
+                0.0
+            };
+            assert_relative_eq!(out[0], expected, epsilon = 1e-12);
+        }
+    }
+
+    #[test]
+    pub fn limiter_does_not_grow_its_buffers() {
+        let mut limiter = Limiter::<f64>::new(48000);
+        limiter.set_lookahead(LIMITER_MAX_LOOKAHEAD);
+        let capacities = (limiter.minimum.capacity(), limiter.average.capacity());
+
+        // Falling peaks keep every frame of the window in the sliding minimum.
+        for n in 0..4000 {
+            let peak = 10.0 - (n % 2000) as f64 / 1000.0;
+            limiter.tick([peak, peak]);
+        }
+        assert_eq!(limiter.minimum.len(), limiter.lookahead);
+        assert_eq!(
+            (limiter.minimum.capacity(), limiter.average.capacity()),
+            capacities
+        );
+    }
+
+    /// Quiet noise floor with a loud burst from `start` to `end`, as a cosine so it is loud from
+    /// its first sample.
+    fn burst(length: usize, start: usize, end: usize) -> Vec<f64> {
+        (0..length)
+            .map(|n| {
+                let amplitude = if n >= start && n < end { 0.5 } else { 0.001 };
+                amplitude * (2.0 * std::f64::consts::PI * 0.01 * (n - start.min(n)) as f64).cos()
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn gate_burst_timing() {
+        let sample_rate = 10000;
+        let mut gate = Gate::<f64>::new(sample_rate);
+        gate.set_threshold(-30.0);
+        gate.set_attack(0.001);
+        gate.set_hold(0.02);
+        gate.set_release(0.01);
+
+        let input = burst(6000, 1000, 3000);
+        let mut open = vec![];
+        let mut gains = vec![];
+        for x in input.iter() {
+            gate.tick([*x, *x]);
+            open.push(gate.is_open());
+            gains.push(gate.gain());
+        }
+
+        // Closed and fully down in the noise floor before the burst.
+        assert!(!open[999]);
+        assert_relative_eq!(gains[999], -80.0, epsilon = 1e-6);
+        // Opens on the first sample of the burst and is all the way up after five time constants.
+        assert!(open[1000]);
+        assert!(gains[1050] > -0.1);
+
+        // The detector takes ln(0.5 / 10^(-33 / 20)) time constants to fall under the threshold
+        // minus hysteresis, then the hold time passes.
+        let fall = (0.01 * sample_rate as f64 * (0.5 / from_db(-33.0)).ln()).ceil() as usize;
+        let close = 3000 + fall + 200;
+        assert!(open[close - 2]);
+        assert!(!open[close + 5]);
+        assert!(gains[close - 2] > -0.1);
+        assert!(gains[close + 500] < -40.0);
+    }
+
+    #[test]
+    pub fn gate_hysteresis() {
+        // A level wobbling 1 dB around the threshold.
+        let transitions = |hysteresis: f64| {
+            let mut gate = Gate::<f64>::new(1000);
+            gate.set_threshold(-30.0);
+            gate.set_hold(0.0);
+            gate.set_hysteresis(hysteresis);
+
+            let mut transitions = 0;
+            let mut was_open = false;
+            for n in 0..2000 {
+                let level = if (n / 50) % 2 == 0 { -29.0 } else { -31.0 };
+                gate.tick([from_db(level); 2]);
+                if gate.is_open() != was_open {
+                    transitions += 1;
+                    was_open = gate.is_open();
+                }
+            }
+            transitions
+        };
+
+        assert!(transitions(0.0) > 30);
+        assert_eq!(transitions(3.0), 1);
+    }
+
+    #[test]
+    pub fn gate_sidechain_filter() {
+        // Loud rumble far below the highpass does not open the gate.
+        let mut gate = Gate::<f64>::new(48000);
+        gate.set_threshold(-30.0);
+        gate.set_sidechain_filter(Some(1000.0), None);
+        let w = 2.0 * std::f64::consts::PI * 30.0 / 48000.0;
+        for n in 0..48000 {
+            gate.tick([0.3 * (w * n as f64).sin(); 2]);
+            assert!(!gate.is_open());
+        }
+
+        gate.set_sidechain_filter(None, None);
+        for n in 0..4800 {
+            gate.tick([0.3 * (w * n as f64).sin(); 2]);
+        }
+        assert!(gate.is_open());
+    }
+
+    #[test]
+    pub fn gate_sidechain_lowpass() {
+        // Cymbal spill far above a kick lowpass keeps the gate shut, even though its rectified
+        // level is mostly DC.
+        let mut gate = Gate::<f64>::new(48000);
+        gate.set_threshold(-30.0);
+        gate.set_sidechain_filter(None, Some(200.0));
+        let w = 2.0 * std::f64::consts::PI * 5000.0 / 48000.0;
+        for n in 0..48000 {
+            let x = 0.3 * (w * n as f64).sin();
+            gate.tick([x, -x]);
+            assert!(!gate.is_open());
+        }
+
+        // The kick itself still opens it.
+        let w = 2.0 * std::f64::consts::PI * 60.0 / 48000.0;
+        for n in 0..4800 {
+            gate.tick([0.3 * (w * n as f64).sin(); 2]);
+        }
+        assert!(gate.is_open());
+    }
+
+    #[test]
+    pub fn expander_ratio() {
+        let mut gate = Gate::<f64>::new(1000);
+        gate.set_threshold(-30.0);
+        gate.set_ratio(2.0);
+
+        let mut out = [0.0; 2];
+        for _ in 0..3000 {
+            out = gate.tick([from_db(-40.0); 2]);
+        }
+        // 10 dB under the threshold at a ratio of 2 is another 10 dB down.
+        assert_relative_eq!(to_db(out[0]), -50.0, epsilon = 1e-6);
+        assert!(!gate.is_open());
+    }
+}
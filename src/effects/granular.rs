@@ -0,0 +1,381 @@
+use dasp::frame::Stereo;
+use dasp::Sample;
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use super::{sample_from_f64, sample_to_f64};
+use crate::delay_line::DelayLine;
+
+/// Most grains playing at once, grains due beyond this are skipped.
+pub const GRANULAR_MAX_GRAINS: usize = 64;
+
+/// Longest and shortest grain length in seconds.
+pub const GRANULAR_MAX_GRAIN_LENGTH: f64 = 1.0;
+pub const GRANULAR_MIN_GRAIN_LENGTH: f64 = 0.001;
+
+struct Grain {
+    // Where it reads, in samples behind the last input.
+    delay: f64,
+    // How far through the grain we are, from 0 to 1.
+    age: f64,
+    // Increment of the age per sample.
+    step: f64,
+    ratio: f64,
+    gains: [f64; 2],
+}
+
+/// Wraps a delay into the part of a frozen buffer that can be read, from 1 to `longest`.
+fn wrap(delay: f64, longest: f64) -> f64 {
+    (delay - 1.0).rem_euclid(longest - 1.0) + 1.0
+}
+
+/// Granular delay that chops the recent input into short windowed grains.
+///
+/// The input is recorded into a long buffer, and grains are started at a steady density, each
+/// reading from around the position with a random spray, with its own randomized length, pitch
+/// and place in the stereo field. Freezing stops the recording, so the grains keep picking from
+/// what is in the buffer at that point, which then loops. All the randomness comes from one
+/// seeded generator, so the same seed and input give the same output.
+pub struct Granular<S> {
+    buffer: DelayLine<Vec<f64>>,
+    grains: Vec<Grain>,
+    rng: StdRng,
+    // Samples until the next grain.
+    countdown: f64,
+    density: f64,
+    // These are in samples.
+    position: f64,
+    spray: f64,
+    length: f64,
+    length_spread: f64,
+    // In semitones.
+    pitch: f64,
+    pitch_spread: f64,
+    pan_spread: f64,
+    freeze: bool,
+    mix: f64,
+    sample_rate: f64,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> Granular<S> {
+    /// `buffer` is how many seconds of input are kept to take grains from.
+    pub fn new(buffer: f64, sample_rate: usize) -> Self {
+        let sample_rate = sample_rate as f64;
+        let capacity = (buffer * sample_rate).ceil() as usize + 4;
+        assert!(capacity > 8);
+
+        let mut granular = Granular {
+            buffer: DelayLine::new(vec![0.0; capacity], 0),
+            grains: Vec::with_capacity(GRANULAR_MAX_GRAINS),
+            rng: StdRng::seed_from_u64(0),
+            countdown: 0.0,
+            density: 0.0,
+            position: 0.0,
+            spray: 0.0,
+            length: 0.0,
+            length_spread: 0.0,
+            pitch: 0.0,
+            pitch_spread: 0.0,
+            pan_spread: 0.0,
+            freeze: false,
+            mix: 1.0,
+            sample_rate,
+            sample: std::marker::PhantomData,
+        };
+        granular.set_density(20.0);
+        granular.set_position(0.2);
+        granular.set_grain_length(0.1);
+        granular
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let dry = [sample_to_f64(in_frame[0]), sample_to_f64(in_frame[1])];
+        if !self.freeze {
+            self.buffer.tick((dry[0] + dry[1]) / 2.0);
+        }
+
+        if self.countdown <= 0.0 {
+            self.countdown += self.sample_rate / self.density;
+            if self.grains.len() < GRANULAR_MAX_GRAINS {
+                let grain = self.spawn();
+                self.grains.push(grain);
+            }
+        }
+        self.countdown -= 1.0;
+
+        // Overlapping grains of unrelated material add up in power rather than amplitude.
+        let overlap = self.density * self.length / self.sample_rate;
+        let gain = 1.0 / overlap.max(1.0).sqrt();
+
+        let mut wet = [0.0; 2];
+        let longest = (self.buffer.capacity() - 3) as f64;
+        let frozen = self.freeze;
+        for grain in self.grains.iter_mut() {
+            let window = (std::f64::consts::PI * grain.age).sin().powi(2);
+            let x = self.buffer.tap_cubic(grain.delay) * window * gain;
+            wet[0] += x * grain.gains[0];
+            wet[1] += x * grain.gains[1];
+
+            grain.age += grain.step;
+            if frozen {
+                // The captured buffer loops, with the oldest input following the newest.
+                grain.delay = wrap(grain.delay - grain.ratio, longest);
+            } else {
+                grain.delay += 1.0 - grain.ratio;
+            }
+        }
+        self.grains.retain(|g| g.age < 1.0);
+
+        [
+            sample_from_f64(dry[0] * (1.0 - self.mix) + wet[0] * self.mix),
+            sample_from_f64(dry[1] * (1.0 - self.mix) + wet[1] * self.mix),
+        ]
+    }
+
+    fn spawn(&mut self) -> Grain {
+        let unit = Uniform::new_inclusive(-1.0, 1.0);
+        let mut length = (self.length * (1.0 + self.length_spread * unit.sample(&mut self.rng)))
+            .max(GRANULAR_MIN_GRAIN_LENGTH * self.sample_rate);
+        let semitones = self.pitch + self.pitch_spread * unit.sample(&mut self.rng);
+        let ratio = 2f64.powf(semitones / 12.0);
+
+        let mut delay = self.position + self.spray * unit.sample(&mut self.rng);
+        let longest = (self.buffer.capacity() - 3) as f64;
+        if self.freeze {
+            delay = wrap(delay, longest);
+        } else {
+            // A grain playing at another speed than the recording drifts through the buffer, so
+            // it is shortened until the drift fits between the input and the end.
+            let drift = (ratio - 1.0).abs();
+            if drift > 0.0 {
+                length = length.min((longest - 1.0) / drift);
+            }
+            // Then it starts where the whole grain stays inside, it moves towards the input when
+            // it plays faster than the recording.
+            let lowest = 1.0 + length * (ratio - 1.0).max(0.0);
+            let highest = longest - length * (1.0 - ratio).max(0.0);
+            delay = delay.max(lowest).min(highest);
+        }
+
+        let pan = self.pan_spread * unit.sample(&mut self.rng);
+        let angle = (pan + 1.0) * std::f64::consts::FRAC_PI_4;
+
+        Grain {
+            delay,
+            age: 0.0,
+            step: 1.0 / length,
+            ratio,
+            gains: [
+                angle.cos() * std::f64::consts::SQRT_2,
+                angle.sin() * std::f64::consts::SQRT_2,
+            ],
+        }
+    }
+
+    /// Number of grains currently playing.
+    pub fn grain_count(&self) -> usize {
+        self.grains.len()
+    }
+
+    /// Grains started per second, at most one per sample.
+    pub fn set_density(&mut self, density: f64) {
+        self.density = density.clamp(0.01, self.sample_rate);
+        self.countdown = self.countdown.min(self.sample_rate / self.density);
+    }
+
+    /// How far back in seconds the grains are taken from.
+    pub fn set_position(&mut self, position: f64) {
+        self.position = position.max(0.0) * self.sample_rate;
+    }
+
+    /// How far in seconds each grain's position may randomly be from the set position, both
+    /// ways.
+    pub fn set_spray(&mut self, spray: f64) {
+        self.spray = spray.max(0.0) * self.sample_rate;
+    }
+
+    /// In seconds, clamped to `GRANULAR_MIN_GRAIN_LENGTH` and `GRANULAR_MAX_GRAIN_LENGTH`.
+    pub fn set_grain_length(&mut self, length: f64) {
+        self.length =
+            length.clamp(GRANULAR_MIN_GRAIN_LENGTH, GRANULAR_MAX_GRAIN_LENGTH) * self.sample_rate;
+    }
+
+    /// Fraction between 0 and 1 by which each grain's length may randomly differ.
+    pub fn set_length_spread(&mut self, spread: f64) {
+        self.length_spread = spread.clamp(0.0, 1.0);
+    }
+
+    /// Transposition of the grains in semitones.
+    pub fn set_pitch(&mut self, pitch: f64) {
+        self.pitch = pitch;
+    }
+
+    /// How many semitones each grain's pitch may randomly differ, both ways.
+    pub fn set_pitch_spread(&mut self, spread: f64) {
+        self.pitch_spread = spread.max(0.0);
+    }
+
+    /// Between 0 for all grains in the centre and 1 for anywhere from left to right.
+    pub fn set_pan_spread(&mut self, spread: f64) {
+        self.pan_spread = spread.clamp(0.0, 1.0);
+    }
+
+    /// Stops recording, so the grains loop over what has been captured.
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.freeze = freeze;
+    }
+
+    /// Reseeds the grain scheduling, so it makes the same grains for the same seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Between 0 for only the input and 1 for only the grains.
+    pub fn set_mix(&mut self, mix: f64) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::tests::measure_frequency;
+    use approx::assert_relative_eq;
+
+    fn sine(frequency: f64, n: usize) -> f64 {
+        (2.0 * std::f64::consts::PI * frequency * n as f64 / 48000.0).sin()
+    }
+
+    #[test]
+    pub fn grains_are_windowed_delayed_input() {
+        let mut granular = Granular::<f64>::new(1.0, 48000);
+        granular.set_density(10.0);
+        granular.set_grain_length(0.05);
+        granular.set_position(0.1);
+
+        // Grains start every 4800 samples, each 2400 long.
+        for n in 0..12000 {
+            let out = granular.tick([sine(440.0, n), sine(440.0, n)]);
+            let age = (n % 4800) as f64 / 2400.0;
+            let expected = if n >= 4800 && age < 1.0 {
+                (std::f64::consts::PI * age).sin().powi(2) * sine(440.0, n - 4800)
+            } else {
+                0.0
+            };
+            assert_relative_eq!(out[0], expected, epsilon = 1e-9);
+            assert_relative_eq!(out[1], expected, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    pub fn pitch_shifts_grains() {
+        let mut granular = Granular::<f64>::new(1.0, 48000);
+        granular.set_density(1.0);
+        granular.set_grain_length(0.2);
+        granular.set_position(0.5);
+        granular.set_pitch(12.0);
+
+        let out: Vec<f64> = (0..60000)
+            .map(|n| granular.tick([sine(500.0, n); 2])[0])
+            .collect();
+
+        // Time the middle of the second grain, the first one had nothing recorded to read yet.
+        // It reads the input at twice its speed.
+        assert_relative_eq!(
+            measure_frequency(&out, 48000 + 2400, 48000 + 7200, 48000),
+            1000.0,
+            max_relative = 1e-3
+        );
+    }
+
+    #[test]
+    pub fn density_is_at_most_one_grain_per_sample() {
+        let mut granular = Granular::<f64>::new(1.0, 48000);
+        granular.set_grain_length(0.001);
+        granular.set_density(1e9);
+        for n in 0..1000 {
+            granular.tick([sine(300.0, n); 2]);
+        }
+
+        // Slowing down again does not leave a backlog of grains to start. At 1000 grains per
+        // second each 48 sample grain overlaps at most the one before it.
+        granular.set_density(1000.0);
+        for n in 0..48 {
+            granular.tick([sine(300.0, n); 2]);
+        }
+        assert!(granular.grain_count() <= 2);
+    }
+
+    #[test]
+    pub fn seeded_scheduling() {
+        let run = |seed: u64| {
+            let mut granular = Granular::<f32>::new(2.0, 48000);
+            granular.set_density(50.0);
+            granular.set_spray(0.1);
+            granular.set_length_spread(0.5);
+            granular.set_pitch_spread(3.0);
+            granular.set_pan_spread(1.0);
+            granular.set_seed(seed);
+            (0..24000)
+                .map(|n| granular.tick([sine(300.0, n) as f32; 2]))
+                .collect::<Vec<_>>()
+        };
+
+        let out = run(7);
+        assert_eq!(out, run(7));
+        assert_ne!(out, run(8));
+        // Panned grains end up in the two channels differently.
+        assert!(out.iter().any(|o| (o[0] - o[1]).abs() > 0.01));
+    }
+
+    #[test]
+    pub fn grains_fit_in_a_short_buffer() {
+        // The default position is further back than this buffer reaches.
+        let mut granular = Granular::<f64>::new(0.1, 48000);
+        for n in 0..4800 {
+            granular.tick([sine(300.0, n); 2]);
+        }
+        granular.set_freeze(true);
+        let loudest = (0..48000)
+            .map(|_| granular.tick([0.0; 2])[0].abs())
+            .fold(0.0, f64::max);
+        assert!(loudest > 0.1);
+
+        // A whole second an octave up drifts a second through a half second buffer.
+        let mut granular = Granular::<f64>::new(0.5, 48000);
+        granular.set_grain_length(1.0);
+        granular.set_pitch(12.0);
+        let out: Vec<f64> = (0..96000)
+            .map(|n| granular.tick([sine(300.0, n); 2])[0])
+            .collect();
+        assert!(out[48000..].iter().any(|o| o.abs() > 0.1));
+    }
+
+    #[test]
+    pub fn freeze_keeps_playing() {
+        let level = |freeze: bool| {
+            let mut granular = Granular::<f64>::new(0.5, 48000);
+            granular.set_spray(0.1);
+            for n in 0..24000 {
+                granular.tick([sine(300.0, n); 2]);
+            }
+
+            // Once the input stops only a frozen buffer still has something to play.
+            granular.set_freeze(freeze);
+            for _ in 0..48000 {
+                granular.tick([0.0; 2]);
+            }
+            let loudest = (0..4800)
+                .map(|_| granular.tick([0.0; 2])[0].abs())
+                .fold(0.0, f64::max);
+            (loudest, granular.grain_count())
+        };
+
+        let (frozen, grains) = level(true);
+        assert!(frozen > 0.1);
+        assert!(grains > 0);
+        assert_eq!(level(false).0, 0.0);
+    }
+}
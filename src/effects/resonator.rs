@@ -0,0 +1,305 @@
+use dasp::Frame;
+use dasp::Sample;
+
+use crate::delay_line::DelayLineFracLin;
+use crate::filter::FIRFilter;
+use crate::instruments::string_loop_filter;
+
+/// Most strings that ring at once, starting a note beyond this takes over an old string. They are
+/// all allocated up front, so playing never allocates.
+pub const RESONATOR_MAX_STRINGS: usize = 12;
+
+/// Lowest string frequency in Hz, which sets the length of the delay lines.
+pub const RESONATOR_MIN_FREQUENCY: f64 = 20.0;
+
+/// Decay in seconds of a string after its note is released.
+const RESONATOR_RELEASE: f64 = 0.05;
+
+/// Frequency in Hz of a MIDI note number.
+fn note_frequency(note: u8) -> f64 {
+    440.0 * 2f64.powf((note as f64 - 69.0) / 12.0)
+}
+
+/// A Karplus-Strong string like `PluckedString`, excited by the input instead of noise.
+struct ResonatorString<T> {
+    delay: DelayLineFracLin<Vec<T>>,
+    filter: FIRFilter<T>,
+    // What the loop filter made of the last output, written back into the delay next tick.
+    feedback: T,
+    // Loss per period, the input is scaled by 1 - rho so a sustained tone at the string's
+    // pitch rings at about the level it comes in at.
+    rho: f64,
+    frequency: f64,
+    note: Option<u8>,
+    released: bool,
+    // Unused strings are skipped until a chord or note takes them.
+    active: bool,
+    // When the string was taken, counted in notes, so the oldest one can be taken over.
+    started: u64,
+}
+
+impl<T: Frame> ResonatorString<T> {
+    fn new(sample_rate: usize) -> Self {
+        let capacity = (sample_rate as f64 / RESONATOR_MIN_FREQUENCY).ceil() as usize + 2;
+        ResonatorString {
+            delay: DelayLineFracLin::new(vec![T::EQUILIBRIUM; capacity], 0.0),
+            filter: FIRFilter::new(vec![0.0; 3]),
+            feedback: T::EQUILIBRIUM,
+            rho: 0.0,
+            frequency: RESONATOR_MIN_FREQUENCY,
+            note: None,
+            released: false,
+            active: false,
+            started: 0,
+        }
+    }
+
+    /// Takes the string for a new pitch, silencing whatever it was still ringing with.
+    fn start(&mut self, frequency: f64, note: Option<u8>, started: u64) {
+        self.delay.clear();
+        self.filter.clear();
+        self.feedback = T::EQUILIBRIUM;
+        self.frequency = frequency.max(RESONATOR_MIN_FREQUENCY);
+        self.note = note;
+        self.released = false;
+        self.active = true;
+        self.started = started;
+    }
+
+    fn tune(&mut self, decay: f64, brightness: f64, sample_rate: usize) {
+        let period = 1.0 / self.frequency;
+        // The loop filter delays by 1 and the feedback by another sample.
+        self.delay
+            .set_delay((sample_rate as f64 * period - 2.0).max(0.0));
+
+        let decay = if self.released {
+            RESONATOR_RELEASE
+        } else {
+            decay
+        };
+        let coefficients = string_loop_filter(period, decay.max(1e-3), brightness);
+        self.rho = coefficients.iter().sum();
+        self.filter
+            .get_mut_coefficients()
+            .copy_from_slice(&coefficients);
+    }
+
+    fn tick(&mut self, input: T) -> T {
+        let excitation = input.scale_amp((1.0 - self.rho).to_sample());
+        let out = self
+            .delay
+            .tick(excitation.add_amp(self.feedback.to_signed_frame()));
+        self.feedback = self.filter.tick(out);
+        out
+    }
+}
+
+/// Strings that ring sympathetically with the input.
+///
+/// Each string is the delay line and loop filter of `PluckedString`, with the input added into
+/// the loop, so only what is close to a string's pitch or its harmonics builds up. The strings
+/// are either tuned to a fixed chord or follow MIDI notes, and released notes are damped quickly
+/// rather than cut off.
+pub struct StringResonator<T> {
+    strings: Vec<ResonatorString<T>>,
+    // Notes started so far.
+    notes: u64,
+    decay: f64,
+    brightness: f64,
+    mix: f64,
+    sample_rate: usize,
+}
+
+impl<T: Frame> StringResonator<T> {
+    pub fn new(sample_rate: usize) -> Self {
+        StringResonator {
+            strings: (0..RESONATOR_MAX_STRINGS)
+                .map(|_| ResonatorString::new(sample_rate))
+                .collect(),
+            notes: 0,
+            decay: 2.0,
+            brightness: 0.5,
+            mix: 0.5,
+            sample_rate,
+        }
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        let mut wet = T::EQUILIBRIUM;
+        for string in self.strings.iter_mut().filter(|s| s.active) {
+            wet = wet.add_amp(string.tick(in_frame).to_signed_frame());
+        }
+
+        in_frame
+            .scale_amp((1.0 - self.mix).to_sample())
+            .add_amp(wet.scale_amp(self.mix.to_sample()).to_signed_frame())
+    }
+
+    /// Replaces all strings with ones at the given frequencies in Hz, which ring until the chord
+    /// is changed.
+    pub fn set_chord(&mut self, frequencies: &[f64]) {
+        for (i, string) in self.strings.iter_mut().enumerate() {
+            match frequencies.get(i) {
+                Some(frequency) => {
+                    string.start(*frequency, None, 0);
+                    string.tune(self.decay, self.brightness, self.sample_rate);
+                }
+                None => string.active = false,
+            }
+        }
+    }
+
+    /// Adds a string at the pitch of a MIDI note. When all strings are taken a released one is
+    /// reused, or else the oldest.
+    pub fn note_on(&mut self, note: u8) {
+        if let Some(string) = self
+            .strings
+            .iter_mut()
+            .find(|s| s.active && s.note == Some(note))
+        {
+            string.released = false;
+            string.tune(self.decay, self.brightness, self.sample_rate);
+            return;
+        }
+
+        // An unused string first, then the oldest released one, then the oldest.
+        self.notes += 1;
+        let string = self
+            .strings
+            .iter_mut()
+            .min_by_key(|s| (s.active, !s.released, s.started))
+            .unwrap();
+        string.start(note_frequency(note), Some(note), self.notes);
+        string.tune(self.decay, self.brightness, self.sample_rate);
+    }
+
+    /// Damps the string of a MIDI note, it keeps its place until another note needs it.
+    pub fn note_off(&mut self, note: u8) {
+        for string in self
+            .strings
+            .iter_mut()
+            .filter(|s| s.active && s.note == Some(note))
+        {
+            string.released = true;
+            string.tune(self.decay, self.brightness, self.sample_rate);
+        }
+    }
+
+    /// Time in seconds for a string to die down by 60 dB.
+    pub fn set_decay(&mut self, decay: f64) {
+        self.decay = decay;
+        self.retune();
+    }
+
+    /// Between 0 for a dull string and 1 for one that keeps all its harmonics.
+    pub fn set_brightness(&mut self, brightness: f64) {
+        self.brightness = brightness.clamp(0.0, 1.0);
+        self.retune();
+    }
+
+    /// Between 0 for only the input and 1 for only the strings.
+    pub fn set_mix(&mut self, mix: f64) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    fn retune(&mut self) {
+        for string in self.strings.iter_mut() {
+            string.tune(self.decay, self.brightness, self.sample_rate);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn impulse_rings_at_string_pitch() {
+        // 375 Hz is a period of exactly 128 samples at 48 kHz.
+        let mut resonator = StringResonator::<f64>::new(48000);
+        resonator.set_chord(&[375.0]);
+        resonator.set_brightness(1.0);
+        resonator.set_decay(0.5);
+        resonator.set_mix(1.0);
+
+        let out: Vec<f64> = (0..25000)
+            .map(|n| resonator.tick(if n == 0 { 1.0 } else { 0.0 }))
+            .collect();
+
+        let rho = (-6.91 / 375.0 / 0.5f64).exp();
+        // The delay line is two samples short of the period, the loop makes up the rest.
+        let first = 126;
+        assert!(out[..first].iter().all(|o| *o == 0.0));
+        assert_relative_eq!(out[first], 1.0 - rho, epsilon = 1e-12);
+        for n in first..first + 128 * 10 {
+            assert_relative_eq!(out[n + 128], out[n] * rho, epsilon = 1e-12);
+        }
+        // Down by 60 dB after the decay time, 187.5 periods.
+        assert_relative_eq!(
+            out[first + 128 * 187] / out[first],
+            1e-3,
+            max_relative = 0.05
+        );
+    }
+
+    #[test]
+    pub fn resonates_near_its_pitch_only() {
+        let level = |frequency: f64| {
+            let mut resonator = StringResonator::<[f64; 2]>::new(48000);
+            resonator.set_chord(&[375.0, 500.0]);
+            resonator.set_mix(1.0);
+            let w = 2.0 * std::f64::consts::PI * frequency / 48000.0;
+            (0..48000)
+                .map(|n| resonator.tick([(w * n as f64).sin(); 2])[0].abs())
+                .skip(24000)
+                .fold(0.0, f64::max)
+        };
+
+        let on_pitch = level(500.0);
+        assert!(on_pitch > 0.5);
+        assert!(level(440.0) < on_pitch / 10.0);
+    }
+
+    #[test]
+    pub fn notes_and_mix() {
+        let mut resonator = StringResonator::<f64>::new(48000);
+        resonator.set_mix(0.0);
+        resonator.note_on(69);
+        assert_relative_eq!(resonator.strings[0].frequency, 440.0);
+        assert_relative_eq!(resonator.tick(0.25), 0.25);
+
+        resonator.set_mix(1.0);
+        for _ in 0..1000 {
+            resonator.tick(0.0);
+        }
+        let ringing = (0..1000)
+            .map(|_| resonator.tick(0.0).abs())
+            .fold(0.0, f64::max);
+        assert!(ringing > 1e-4);
+
+        // Released it dies down in a fraction of the decay time.
+        resonator.note_off(69);
+        for _ in 0..4800 {
+            resonator.tick(0.0);
+        }
+        let released = (0..1000)
+            .map(|_| resonator.tick(0.0).abs())
+            .fold(0.0, f64::max);
+        assert!(released < ringing * 1e-3);
+
+        // New notes take the unused strings, then the released one.
+        let last = 40 + RESONATOR_MAX_STRINGS as u8 - 1;
+        for note in 40..=last {
+            resonator.note_on(note);
+        }
+        assert!(resonator.strings.iter().all(|s| s.active));
+        assert!(resonator.strings.iter().all(|s| s.note != Some(69)));
+        assert_eq!(resonator.strings[0].note, Some(last));
+
+        // After that the oldest note.
+        resonator.note_on(90);
+        assert_eq!(resonator.strings[1].note, Some(90));
+        assert!(resonator.strings.iter().all(|s| s.note != Some(40)));
+    }
+}
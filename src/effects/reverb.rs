@@ -0,0 +1,531 @@
+use dasp::frame::Stereo;
+use dasp::Sample;
+
+use super::{sample_from_f64, sample_to_f64};
+use crate::delay_line::DelayLine;
+use crate::lfo::{Lfo, Waveform};
+
+// The Freeverb tunings, in samples at 44.1 kHz.
+const FREEVERB_SAMPLE_RATE: f64 = 44100.0;
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+
+const FIXED_GAIN: f64 = 0.015;
+const SCALE_WET: f64 = 3.0;
+const SCALE_DRY: f64 = 2.0;
+const SCALE_DAMPING: f64 = 0.4;
+const SCALE_ROOM: f64 = 0.28;
+const OFFSET_ROOM: f64 = 0.7;
+
+/// Feedback comb filter with a one pole lowpass in the loop.
+struct Comb {
+    delay_line: DelayLine<Vec<f64>>,
+    length: usize,
+    filter_store: f64,
+}
+
+impl Comb {
+    fn new(length: usize) -> Self {
+        Comb {
+            delay_line: DelayLine::new(vec![0.0; length + 1], 0),
+            length,
+            filter_store: 0.0,
+        }
+    }
+
+    fn tick(&mut self, input: f64, feedback: f64, damping: f64) -> f64 {
+        let out = self.delay_line.tap(self.length - 1);
+        self.filter_store = out * (1.0 - damping) + self.filter_store * damping;
+        self.delay_line.tick(input + self.filter_store * feedback);
+        out
+    }
+}
+
+/// The Schroeder allpass approximation used by Freeverb, with a fixed gain of 0.5.
+struct Allpass {
+    delay_line: DelayLine<Vec<f64>>,
+    length: usize,
+}
+
+impl Allpass {
+    fn new(length: usize) -> Self {
+        Allpass {
+            delay_line: DelayLine::new(vec![0.0; length + 1], 0),
+            length,
+        }
+    }
+
+    fn tick(&mut self, input: f64) -> f64 {
+        let delayed = self.delay_line.tap(self.length - 1);
+        self.delay_line.tick(input + delayed * 0.5);
+        delayed - input
+    }
+}
+
+/// Stereo reverb in the style of Freeverb: eight parallel damped combs followed by four series
+/// allpasses per channel, with the right channel's delays slightly longer than the left's.
+pub struct Reverb<S> {
+    combs: [Vec<Comb>; 2],
+    allpasses: [Vec<Allpass>; 2],
+    room_size: f64,
+    damping: f64,
+    width: f64,
+    wet: f64,
+    dry: f64,
+    freeze: bool,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> Reverb<S> {
+    pub fn new(sample_rate: usize) -> Self {
+        let scale = |length: usize| {
+            ((length as f64 * sample_rate as f64 / FREEVERB_SAMPLE_RATE).round() as usize).max(1)
+        };
+        let combs = |spread: usize| {
+            COMB_TUNINGS
+                .iter()
+                .map(|l| Comb::new(scale(l + spread)))
+                .collect()
+        };
+        let allpasses = |spread: usize| {
+            ALLPASS_TUNINGS
+                .iter()
+                .map(|l| Allpass::new(scale(l + spread)))
+                .collect()
+        };
+
+        Reverb {
+            combs: [combs(0), combs(STEREO_SPREAD)],
+            allpasses: [allpasses(0), allpasses(STEREO_SPREAD)],
+            room_size: 0.5,
+            damping: 0.5,
+            width: 1.0,
+            wet: 1.0 / 3.0,
+            dry: 0.0,
+            freeze: false,
+            sample: std::marker::PhantomData,
+        }
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let dry = [sample_to_f64(in_frame[0]), sample_to_f64(in_frame[1])];
+
+        let (input_gain, feedback, damping) = if self.freeze {
+            (0.0, 1.0, 0.0)
+        } else {
+            (
+                FIXED_GAIN,
+                self.room_size * SCALE_ROOM + OFFSET_ROOM,
+                self.damping * SCALE_DAMPING,
+            )
+        };
+        let input = (dry[0] + dry[1]) * input_gain;
+
+        let mut wet = [0.0; 2];
+        for (channel, out) in wet.iter_mut().enumerate() {
+            *out = self.combs[channel]
+                .iter_mut()
+                .map(|c| c.tick(input, feedback, damping))
+                .sum();
+            for allpass in self.allpasses[channel].iter_mut() {
+                *out = allpass.tick(*out);
+            }
+        }
+
+        let wet_gain = self.wet * SCALE_WET;
+        let wet1 = wet_gain * (self.width / 2.0 + 0.5);
+        let wet2 = wet_gain * ((1.0 - self.width) / 2.0);
+        let dry_gain = self.dry * SCALE_DRY;
+
+        [
+            sample_from_f64(wet[0] * wet1 + wet[1] * wet2 + dry[0] * dry_gain),
+            sample_from_f64(wet[1] * wet1 + wet[0] * wet2 + dry[1] * dry_gain),
+        ]
+    }
+
+    /// From 0 to 1, longer decay for larger rooms.
+    pub fn set_room_size(&mut self, room_size: f64) {
+        self.room_size = room_size.clamp(0.0, 1.0);
+    }
+
+    /// From 0 to 1, how quickly the high frequencies decay compared to the low ones.
+    pub fn set_damping(&mut self, damping: f64) {
+        self.damping = damping.clamp(0.0, 1.0);
+    }
+
+    /// Stereo width of the reverb from 0 (mono) to 1.
+    pub fn set_width(&mut self, width: f64) {
+        self.width = width.clamp(0.0, 1.0);
+    }
+
+    pub fn set_wet(&mut self, wet: f64) {
+        self.wet = wet.clamp(0.0, 1.0);
+    }
+
+    pub fn set_dry(&mut self, dry: f64) {
+        self.dry = dry.clamp(0.0, 1.0);
+    }
+
+    /// While frozen no new input gets into the reverb, and the sound already in it rings forever.
+    pub fn set_freeze(&mut self, freeze: bool) {
+        self.freeze = freeze;
+    }
+}
+
+pub const FDN_MIN_LINES: usize = 4;
+pub const FDN_MAX_LINES: usize = 16;
+
+// The shortest and longest delay line of the network, in seconds.
+const FDN_SHORTEST: f64 = 0.021;
+const FDN_LONGEST: f64 = 0.053;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeedbackMatrix {
+    /// Maximally mixing, needs a power of two number of lines.
+    Hadamard,
+    /// I - 2/N * ones, cheap and works for any number of lines.
+    Householder,
+}
+
+impl FeedbackMatrix {
+    /// Multiplies `values` by the (orthogonal) matrix in place.
+    fn apply(self, values: &mut [f64]) {
+        let n = values.len();
+        match self {
+            FeedbackMatrix::Hadamard => {
+                // Fast Walsh-Hadamard transform
+                let mut h = 1;
+                while h < n {
+                    for i in (0..n).step_by(2 * h) {
+                        for j in i..i + h {
+                            let (a, b) = (values[j], values[j + h]);
+                            values[j] = a + b;
+                            values[j + h] = a - b;
+                        }
+                    }
+                    h *= 2;
+                }
+                let scale = 1.0 / (n as f64).sqrt();
+                for v in values.iter_mut() {
+                    *v *= scale;
+                }
+            }
+            FeedbackMatrix::Householder => {
+                let sum = values.iter().sum::<f64>() * 2.0 / n as f64;
+                for v in values.iter_mut() {
+                    *v -= sum;
+                }
+            }
+        }
+    }
+}
+
+struct FdnLine {
+    delay_line: DelayLine<Vec<f64>>,
+    length: usize,
+    // Absorption filter g * (1 - pole) / (1 - pole * z^-1)
+    gain: f64,
+    pole: f64,
+    filter_state: f64,
+    lfo: Lfo,
+}
+
+#[rustfmt::skip]
+fn is_prime(n: usize) -> bool {
+    n >= 2 && (2..).take_while(|d| d * d <= n).all(|d| !n.is_multiple_of(d))
+}
+
+/// Feedback delay network reverb.
+///
+/// The delay lines have mutually prime lengths, each followed by a first order absorption filter
+/// set from the wanted reverberation time at low and high frequencies (Jot's method), and are
+/// mixed by an orthogonal feedback matrix. A slow modulation of the read positions breaks up
+/// the metallic ringing of the network's modes.
+pub struct Fdn<S> {
+    lines: Vec<FdnLine>,
+    matrix: FeedbackMatrix,
+    outputs: Vec<f64>,
+    // What goes back into the lines, kept here so ticking does not allocate.
+    feedback: Vec<f64>,
+    modulation_depth: f64,
+    mix: f64,
+    sample_rate: f64,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> Fdn<S> {
+    /// `lines` is clamped to between `FDN_MIN_LINES` and `FDN_MAX_LINES`, and must be a power of
+    /// two for the Hadamard matrix.
+    pub fn new(lines: usize, matrix: FeedbackMatrix, sample_rate: usize) -> Self {
+        let count = lines.clamp(FDN_MIN_LINES, FDN_MAX_LINES);
+        assert!(matrix != FeedbackMatrix::Hadamard || count.is_power_of_two());
+
+        let sample_rate = sample_rate as f64;
+        let lines = (0..count)
+            .map(|i| {
+                // Spread the lengths geometrically, then move each up to a prime.
+                let spread = i as f64 / (count - 1) as f64;
+                let seconds = FDN_SHORTEST * (FDN_LONGEST / FDN_SHORTEST).powf(spread);
+                let mut length = (seconds * sample_rate) as usize;
+                while !is_prime(length) {
+                    length += 1;
+                }
+
+                let mut lfo = Lfo::new(Waveform::Sine, 0.0, sample_rate as usize);
+                lfo.set_phase_offset(i as f64 / count as f64);
+
+                FdnLine {
+                    // Room for the modulation on top of the length.
+                    delay_line: DelayLine::new(vec![0.0; length + 64], 0),
+                    length,
+                    gain: 0.0,
+                    pole: 0.0,
+                    filter_state: 0.0,
+                    lfo,
+                }
+            })
+            .collect();
+
+        let mut fdn = Fdn {
+            lines,
+            matrix,
+            outputs: vec![0.0; count],
+            feedback: vec![0.0; count],
+            modulation_depth: 0.0,
+            mix: 0.3,
+            sample_rate,
+            sample: std::marker::PhantomData,
+        };
+        fdn.set_decay(2.0, 1.0);
+        fdn.set_modulation(0.0002, 0.5);
+        fdn
+    }
+
+    /// Time in seconds for the reverb to decay by 60 dB at DC and at Nyquist.
+    pub fn set_decay(&mut self, rt60_low: f64, rt60_high: f64) {
+        let sample_rate = self.sample_rate;
+        for line in self.lines.iter_mut() {
+            // The gain for one trip through the line that gives the wanted decay.
+            let gain = |rt60: f64| 10f64.powf(-3.0 * line.length as f64 / (rt60 * sample_rate));
+            let low = gain(rt60_low);
+            let high = gain(rt60_high);
+
+            line.gain = low;
+            line.pole = (low - high) / (low + high);
+        }
+    }
+
+    /// Depth in seconds and rate in Hz of the delay modulation. The lines run at slightly
+    /// different rates.
+    pub fn set_modulation(&mut self, depth: f64, rate: f64) {
+        // Limited to what fits in the extra room allocated for each line.
+        self.modulation_depth = (depth * self.sample_rate).clamp(0.0, 30.0);
+        for (i, line) in self.lines.iter_mut().enumerate() {
+            line.lfo.set_rate(rate * (1.0 + 0.1 * i as f64));
+        }
+    }
+
+    /// Balance between dry (0) and reverberated (1) signal.
+    pub fn set_mix(&mut self, mix: f64) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let dry = [sample_to_f64(in_frame[0]), sample_to_f64(in_frame[1])];
+        let input = (dry[0] + dry[1]) / 2.0;
+
+        for (line, out) in self.lines.iter_mut().zip(self.outputs.iter_mut()) {
+            // Read before writing, so the loop is `length` long.
+            let delay = line.length as f64 - 1.0 + self.modulation_depth * line.lfo.tick();
+            let delayed = line.delay_line.tap_cubic(delay);
+
+            line.filter_state =
+                line.gain * (1.0 - line.pole) * delayed + line.pole * line.filter_state;
+            *out = line.filter_state;
+        }
+
+        let count = self.lines.len();
+        let mut wet = [0.0; 2];
+        for (i, out) in self.outputs.iter().enumerate() {
+            wet[i % 2] += out;
+        }
+        let output_gain = 1.0 / (count as f64 / 2.0).sqrt();
+
+        self.feedback.copy_from_slice(&self.outputs);
+        self.matrix.apply(&mut self.feedback);
+        for (i, (line, f)) in self.lines.iter_mut().zip(self.feedback.iter()).enumerate() {
+            let sign = if (i / 2) % 2 == 0 { 1.0 } else { -1.0 };
+            line.delay_line.tick(input * sign + f);
+        }
+
+        [
+            sample_from_f64(dry[0] * (1.0 - self.mix) + wet[0] * output_gain * self.mix),
+            sample_from_f64(dry[1] * (1.0 - self.mix) + wet[1] * output_gain * self.mix),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tail_energy(reverb: &mut Reverb<f64>, from: usize, to: usize) -> f64 {
+        (0..to)
+            .map(|n| reverb.tick(if n == 0 { [1.0, 1.0] } else { [0.0, 0.0] }))
+            .skip(from)
+            .map(|o| o[0] * o[0] + o[1] * o[1])
+            .sum()
+    }
+
+    #[test]
+    pub fn comb_lengths_follow_sample_rate() {
+        let reverb = Reverb::<f32>::new(88200);
+
+        assert_eq!(reverb.combs[0][0].length, 2 * 1116);
+        assert_eq!(reverb.combs[1][0].length, 2 * (1116 + 23));
+        assert_eq!(reverb.allpasses[0][3].length, 2 * 225);
+    }
+
+    #[test]
+    pub fn larger_rooms_ring_longer() {
+        let mut small = Reverb::new(48000);
+        small.set_room_size(0.2);
+        let mut large = Reverb::new(48000);
+        large.set_room_size(0.9);
+
+        let small_tail = tail_energy(&mut small, 48000, 96000);
+        let large_tail = tail_energy(&mut large, 48000, 96000);
+
+        assert!(small_tail > 0.0);
+        assert!(large_tail > 100.0 * small_tail);
+    }
+
+    #[test]
+    pub fn freeze_holds_the_tail() {
+        let mut reverb = Reverb::<f64>::new(48000);
+        reverb.set_damping(0.7);
+
+        for n in 0..4800 {
+            let x = if n % 100 == 0 { 1.0 } else { 0.0 };
+            reverb.tick([x, x]);
+        }
+        reverb.set_freeze(true);
+
+        let energy = |reverb: &mut Reverb<f64>| -> f64 {
+            (0..48000)
+                .map(|n| {
+                    // Input is ignored while frozen.
+                    let x = if n % 100 == 0 { 1.0 } else { 0.0 };
+                    reverb.tick([x, x])
+                })
+                .map(|o| o[0] * o[0] + o[1] * o[1])
+                .sum()
+        };
+        let first = energy(&mut reverb);
+        let later = energy(&mut reverb);
+
+        assert!(first > 0.0);
+        approx::assert_relative_eq!(first, later, max_relative = 0.05);
+    }
+
+    #[test]
+    pub fn zero_width_is_mono() {
+        let mut reverb = Reverb::<f64>::new(44100);
+        reverb.set_width(0.0);
+
+        for n in 0..10000 {
+            let x = (n as f64 * 0.01).sin();
+            let out = reverb.tick([x, -0.3 * x]);
+            approx::assert_relative_eq!(out[0], out[1]);
+        }
+    }
+
+    /// Estimates the RT60 of an impulse response from the slope of its Schroeder decay curve
+    /// between -5 and -35 dB.
+    fn rt60(response: &[f64], sample_rate: f64) -> f64 {
+        let mut energy: Vec<f64> = response
+            .iter()
+            .rev()
+            .scan(0.0, |sum, v| {
+                *sum += v * v;
+                Some(*sum)
+            })
+            .collect();
+        energy.reverse();
+
+        let db: Vec<f64> = energy
+            .iter()
+            .map(|e| 10.0 * (e / energy[0]).log10())
+            .collect();
+        let start = db.iter().position(|d| *d < -5.0).unwrap();
+        let end = db.iter().position(|d| *d < -35.0).unwrap();
+
+        (end - start) as f64 / sample_rate * 60.0 / 30.0
+    }
+
+    fn fdn_response(fdn: &mut Fdn<f64>, length: usize) -> Vec<f64> {
+        fdn.set_mix(1.0);
+        (0..length)
+            .map(|n| fdn.tick(if n == 0 { [1.0, 1.0] } else { [0.0, 0.0] })[0])
+            .collect()
+    }
+
+    #[test]
+    pub fn feedback_matrices_are_orthogonal() {
+        for (matrix, n) in [
+            (FeedbackMatrix::Hadamard, 16),
+            (FeedbackMatrix::Householder, 7),
+        ]
+        .iter()
+        {
+            let mut values: Vec<f64> = (0..*n).map(|i| (i as f64 * 1.3).sin()).collect();
+            let norm: f64 = values.iter().map(|v| v * v).sum();
+            matrix.apply(&mut values);
+            approx::assert_relative_eq!(values.iter().map(|v| v * v).sum::<f64>(), norm);
+        }
+    }
+
+    #[test]
+    pub fn fdn_decay_time() {
+        let sample_rate = 48000;
+        for (lines, matrix) in [
+            (4, FeedbackMatrix::Hadamard),
+            (16, FeedbackMatrix::Hadamard),
+            (6, FeedbackMatrix::Householder),
+        ]
+        .iter()
+        {
+            let mut fdn = Fdn::new(*lines, *matrix, sample_rate);
+            fdn.set_decay(1.0, 1.0);
+            let response = fdn_response(&mut fdn, 2 * sample_rate);
+
+            approx::assert_relative_eq!(
+                rt60(&response, sample_rate as f64),
+                1.0,
+                max_relative = 0.1
+            );
+        }
+    }
+
+    #[test]
+    pub fn fdn_frequency_dependent_decay() {
+        use crate::filter::{Biquad, BiquadCoefficients};
+
+        let sample_rate = 48000;
+        let mut fdn = Fdn::new(8, FeedbackMatrix::Hadamard, sample_rate);
+        fdn.set_decay(2.0, 0.5);
+        let response = fdn_response(&mut fdn, 3 * sample_rate);
+
+        let mut lowpass = Biquad::<f64>::new(BiquadCoefficients::lowpass(200.0, 0.7, sample_rate));
+        let mut highpass =
+            Biquad::<f64>::new(BiquadCoefficients::highpass(15000.0, 0.7, sample_rate));
+        let low: Vec<f64> = response.iter().map(|v| lowpass.tick(*v)).collect();
+        let high: Vec<f64> = response.iter().map(|v| highpass.tick(*v)).collect();
+
+        let low_rt60 = rt60(&low, sample_rate as f64);
+        let high_rt60 = rt60(&high, sample_rate as f64);
+        approx::assert_relative_eq!(low_rt60, 2.0, max_relative = 0.15);
+        assert!(high_rt60 < 0.5 * low_rt60);
+    }
+}
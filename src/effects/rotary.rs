@@ -0,0 +1,282 @@
+use dasp::frame::Stereo;
+use dasp::Sample;
+
+use super::{sample_from_f64, sample_to_f64};
+use crate::delay_line::DelayLine;
+use crate::filter::Crossover;
+use crate::room::SPEED_OF_SOUND;
+
+/// Where the signal is split between the drum and the horn, in Hz.
+pub const ROTARY_CROSSOVER: f64 = 800.0;
+
+/// A rotating speaker or baffle, heard from a number of fixed microphones.
+struct Rotor {
+    delay_line: DelayLine<Vec<f64>>,
+    // In cycles.
+    angle: f64,
+    // In Hz.
+    speed: f64,
+    slow: f64,
+    fast: f64,
+    // One pole coefficients for speeding up and slowing down.
+    acceleration: f64,
+    brake: f64,
+    // Distance from the axis to the mouth, in samples of sound travel.
+    radius: f64,
+    // How much quieter the rotor is facing away from a microphone than facing it.
+    directivity: f64,
+    sample_rate: f64,
+}
+
+impl Rotor {
+    fn new(slow: f64, fast: f64, radius: f64, directivity: f64, sample_rate: usize) -> Self {
+        let radius = radius / SPEED_OF_SOUND * sample_rate as f64;
+        Rotor {
+            delay_line: DelayLine::new(vec![0.0; (2.0 * radius).ceil() as usize + 5], 0),
+            angle: 0.0,
+            speed: slow,
+            slow,
+            fast,
+            acceleration: 0.0,
+            brake: 0.0,
+            radius,
+            directivity,
+            sample_rate: sample_rate as f64,
+        }
+    }
+
+    /// Time constants in seconds for reaching a higher and a lower speed.
+    fn set_inertia(&mut self, acceleration: f64, brake: f64) {
+        let sample_rate = self.sample_rate;
+        let coefficient = |time: f64| 1.0 - (-1.0 / (time.max(1e-6) * sample_rate)).exp();
+        self.acceleration = coefficient(acceleration);
+        self.brake = coefficient(brake);
+    }
+
+    /// The rotor as heard from microphones at the given angles, in cycles.
+    fn tick(&mut self, input: f64, fast: bool, microphones: [f64; 2]) -> [f64; 2] {
+        self.delay_line.tick(input);
+
+        let target = if fast { self.fast } else { self.slow };
+        let coefficient = if target > self.speed {
+            self.acceleration
+        } else {
+            self.brake
+        };
+        self.speed += (target - self.speed) * coefficient;
+        self.angle = (self.angle + self.speed / self.sample_rate).fract();
+
+        let mut out = [0.0; 2];
+        for (o, microphone) in out.iter_mut().zip(microphones.iter()) {
+            // 1 when the mouth points at the microphone, which is also when it is closest.
+            let facing = (2.0 * std::f64::consts::PI * (self.angle - microphone)).cos();
+            let delay = self.radius * (1.0 - facing) + 1.0;
+            let gain = 1.0 - self.directivity * (1.0 - facing) / 2.0;
+            *o = self.delay_line.tap_cubic(delay) * gain;
+        }
+        out
+    }
+}
+
+/// Rotating speaker cabinet in the style of a Leslie.
+///
+/// The input is split into a drum rotor for the lows and a horn rotor for the highs, each turning
+/// at its own slow or fast speed. Their movement towards and away from the two microphones gives
+/// the Doppler shift and their directivity the amplitude modulation. Switching speed ramps each
+/// rotor with its own inertia, the heavy drum much slower than the horn.
+pub struct Rotary<S> {
+    crossover: Crossover<f64>,
+    drum: Rotor,
+    horn: Rotor,
+    fast: bool,
+    microphones: [f64; 2],
+    horn_level: f64,
+    drum_level: f64,
+    sample: std::marker::PhantomData<S>,
+}
+
+impl<S: Sample> Rotary<S> {
+    pub fn new(sample_rate: usize) -> Self {
+        let mut drum = Rotor::new(0.7, 5.7, 0.2, 0.3, sample_rate);
+        drum.set_inertia(4.0, 3.0);
+        // Start the rotors out of line with each other.
+        drum.angle = 0.3;
+        let mut horn = Rotor::new(0.8, 6.7, 0.15, 0.6, sample_rate);
+        horn.set_inertia(0.7, 0.8);
+
+        let mut rotary = Rotary {
+            crossover: Crossover::new(&[ROTARY_CROSSOVER], sample_rate),
+            drum,
+            horn,
+            fast: false,
+            microphones: [0.0; 2],
+            horn_level: 1.0,
+            drum_level: 1.0,
+            sample: std::marker::PhantomData,
+        };
+        rotary.set_mic_spread(180.0);
+        rotary
+    }
+
+    pub fn tick(&mut self, in_frame: Stereo<S>) -> Stereo<S> {
+        let input = (sample_to_f64(in_frame[0]) + sample_to_f64(in_frame[1])) / 2.0;
+        let bands = self.crossover.tick(input);
+        let (low, high) = (bands[0], bands[1]);
+
+        let drum = self.drum.tick(low, self.fast, self.microphones);
+        let horn = self.horn.tick(high, self.fast, self.microphones);
+
+        [
+            sample_from_f64(drum[0] * self.drum_level + horn[0] * self.horn_level),
+            sample_from_f64(drum[1] * self.drum_level + horn[1] * self.horn_level),
+        ]
+    }
+
+    /// Switches between the slow and fast speeds. The rotors take a while to get there.
+    pub fn set_fast(&mut self, fast: bool) {
+        self.fast = fast;
+    }
+
+    pub fn is_fast(&self) -> bool {
+        self.fast
+    }
+
+    /// Current horn speed in Hz.
+    pub fn horn_speed(&self) -> f64 {
+        self.horn.speed
+    }
+
+    /// Current drum speed in Hz.
+    pub fn drum_speed(&self) -> f64 {
+        self.drum.speed
+    }
+
+    /// Slow and fast horn speeds in Hz.
+    pub fn set_horn_speeds(&mut self, slow: f64, fast: f64) {
+        self.horn.slow = slow;
+        self.horn.fast = fast;
+    }
+
+    /// Slow and fast drum speeds in Hz.
+    pub fn set_drum_speeds(&mut self, slow: f64, fast: f64) {
+        self.drum.slow = slow;
+        self.drum.fast = fast;
+    }
+
+    /// Time constants in seconds for the horn to speed up and to slow down.
+    pub fn set_horn_inertia(&mut self, acceleration: f64, brake: f64) {
+        self.horn.set_inertia(acceleration, brake);
+    }
+
+    /// Time constants in seconds for the drum to speed up and to slow down.
+    pub fn set_drum_inertia(&mut self, acceleration: f64, brake: f64) {
+        self.drum.set_inertia(acceleration, brake);
+    }
+
+    /// Angle between the two microphones around the cabinet in degrees. At 0 the output is mono.
+    pub fn set_mic_spread(&mut self, degrees: f64) {
+        let half = degrees / 360.0 / 2.0;
+        self.microphones = [-half, half];
+    }
+
+    pub fn set_levels(&mut self, horn: f64, drum: f64) {
+        self.horn_level = horn;
+        self.drum_level = drum;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::tests::rising_zero_crossings;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn rotors_ramp_with_inertia() {
+        let sample_rate = 2000;
+        let mut rotary = Rotary::<f64>::new(sample_rate);
+        rotary.set_fast(true);
+
+        // One horn time constant gets it 1 - 1/e of the way, the drum is still far behind.
+        for _ in 0..(0.7 * sample_rate as f64) as usize {
+            rotary.tick([0.0, 0.0]);
+        }
+        let horn = (rotary.horn_speed() - 0.8) / (6.7 - 0.8);
+        let drum = (rotary.drum_speed() - 0.7) / (5.7 - 0.7);
+        assert_relative_eq!(horn, 1.0 - (-1.0f64).exp(), epsilon = 1e-3);
+        assert!(drum < 0.2);
+
+        for _ in 0..60 * sample_rate {
+            rotary.tick([0.0, 0.0]);
+        }
+        assert_relative_eq!(rotary.horn_speed(), 6.7, epsilon = 1e-3);
+        assert_relative_eq!(rotary.drum_speed(), 5.7, epsilon = 1e-3);
+
+        rotary.set_fast(false);
+        for _ in 0..60 * sample_rate {
+            rotary.tick([0.0, 0.0]);
+        }
+        assert_relative_eq!(rotary.horn_speed(), 0.8, epsilon = 1e-3);
+        assert_relative_eq!(rotary.drum_speed(), 0.7, epsilon = 1e-3);
+    }
+
+    #[test]
+    pub fn horn_doppler() {
+        let sample_rate = 48000;
+        let mut rotary = Rotary::<f64>::new(sample_rate);
+        rotary.set_levels(1.0, 0.0);
+        rotary.set_horn_inertia(0.0, 0.0);
+        rotary.set_fast(true);
+
+        let frequency = 3000.0;
+        let w = 2.0 * std::f64::consts::PI * frequency / sample_rate as f64;
+        let out: Vec<f64> = (0..sample_rate)
+            .map(|n| rotary.tick([(w * n as f64).sin(); 2])[0])
+            .collect();
+
+        let highest = rising_zero_crossings(&out, 1000, out.len())
+            .windows(2)
+            .map(|c| sample_rate as f64 / (c[1] - c[0]))
+            .fold(0.0, f64::max);
+
+        // The mouth moves at up to 2 pi r times the rotation rate.
+        let speed = 2.0 * std::f64::consts::PI * 0.15 * 6.7;
+        assert_relative_eq!(
+            highest / frequency - 1.0,
+            speed / SPEED_OF_SOUND,
+            max_relative = 0.1
+        );
+    }
+
+    #[test]
+    pub fn mic_spread() {
+        let w = 2.0 * std::f64::consts::PI * 2000.0 / 48000.0;
+        let run = |spread: f64| {
+            let mut rotary = Rotary::<f64>::new(48000);
+            rotary.set_mic_spread(spread);
+            (0..48000)
+                .map(|n| rotary.tick([(w * n as f64).sin(); 2]))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(run(0.0).iter().all(|o| o[0] == o[1]));
+
+        // Facing away from each other the microphones hear the horn's loudness swap between them.
+        let out = run(180.0);
+        let levels: Vec<(f64, f64)> = out[4800..]
+            .chunks(480)
+            .map(|c| {
+                let power = |channel: usize| c.iter().map(|o| o[channel] * o[channel]).sum::<f64>();
+                (power(0), power(1))
+            })
+            .collect();
+        let mean =
+            |f: fn(&(f64, f64)) -> f64| levels.iter().map(f).sum::<f64>() / levels.len() as f64;
+        let (mean_left, mean_right) = (mean(|l| l.0), mean(|l| l.1));
+        let covariance: f64 = levels
+            .iter()
+            .map(|(l, r)| (l - mean_left) * (r - mean_right))
+            .sum();
+        assert!(covariance < 0.0);
+    }
+}
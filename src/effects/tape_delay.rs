@@ -0,0 +1,329 @@
+use dasp::Frame;
+use dasp::Sample;
+
+use super::{sample_from_f64, sample_to_f64};
+use crate::delay_line::DelayLine;
+use crate::filter::{Biquad, BiquadCoefficients};
+use crate::lfo::{Lfo, Waveform};
+
+/// Longest delay in seconds, including the wow and flutter.
+pub const TAPE_DELAY_MAX_DELAY: f64 = 2.0;
+
+/// Above 1 the repeats build up until the saturation holds them back.
+pub const TAPE_DELAY_MAX_FEEDBACK: f64 = 1.1;
+
+/// Echo from a tape loop between a record and a playback head.
+///
+/// The tape speed wanders slowly and randomly (wow) and quickly and regularly (flutter), which
+/// moves the read position. Everything recorded goes through a tanh saturation, and the repeats
+/// go through a band limiting filter on their way back to the record head, so each one comes
+/// back darker and thinner. A new delay time is reached by gliding the head, which bends the
+/// pitch of what is playing the way changing the tape speed would.
+pub struct TapeDelay<T> {
+    delay_line: DelayLine<Vec<T>>,
+    // In samples.
+    delay: f64,
+    target: f64,
+    glide: f64,
+    wow: Lfo,
+    flutter: Lfo,
+    // In samples.
+    wow_depth: f64,
+    flutter_depth: f64,
+    drive: f64,
+    feedback: f64,
+    lowpass: Biquad<T>,
+    highpass: Biquad<T>,
+    mix: f64,
+    sample_rate: usize,
+}
+
+impl<T: Frame> TapeDelay<T> {
+    /// `delay` in seconds.
+    pub fn new(delay: f64, sample_rate: usize) -> Self {
+        let capacity = (TAPE_DELAY_MAX_DELAY * sample_rate as f64).ceil() as usize + 4;
+        let mut tape = TapeDelay {
+            delay_line: DelayLine::new(vec![T::EQUILIBRIUM; capacity], 0),
+            delay: 0.0,
+            target: 0.0,
+            glide: 1.0,
+            wow: Lfo::new(Waveform::SmoothRandom, 0.7, sample_rate),
+            flutter: Lfo::new(Waveform::Sine, 7.0, sample_rate),
+            wow_depth: 0.0,
+            flutter_depth: 0.0,
+            drive: 1.0,
+            feedback: 0.4,
+            lowpass: Biquad::new(BiquadCoefficients::lowpass(
+                3500.0,
+                std::f64::consts::FRAC_1_SQRT_2,
+                sample_rate,
+            )),
+            highpass: Biquad::new(BiquadCoefficients::highpass(
+                100.0,
+                std::f64::consts::FRAC_1_SQRT_2,
+                sample_rate,
+            )),
+            mix: 0.5,
+            sample_rate,
+        };
+        // The depths decide how much room the delay needs, so they go first.
+        tape.set_wow(0.0015);
+        tape.set_flutter(0.0001);
+        tape.set_delay(delay);
+        tape.delay = tape.target;
+        tape.set_glide(0.2);
+        tape
+    }
+
+    pub fn tick(&mut self, in_frame: T) -> T {
+        self.delay += (self.target - self.delay) * self.glide;
+        let modulation =
+            self.wow_depth * self.wow.tick() + self.flutter_depth * self.flutter.tick();
+
+        // Read before writing, so the feedback loop is exactly as long as the delay.
+        let out = self.delay_line.tap_cubic(self.delay + modulation - 1.0);
+
+        let recirculated = self.highpass.tick(self.lowpass.tick(out));
+        let drive = self.drive;
+        let recorded = in_frame
+            .add_amp(
+                recirculated
+                    .scale_amp(self.feedback.to_sample())
+                    .to_signed_frame(),
+            )
+            .map(|s| sample_from_f64((drive * sample_to_f64(s)).tanh() / drive));
+        self.delay_line.tick(recorded);
+
+        in_frame
+            .scale_amp((1.0 - self.mix).to_sample())
+            .add_amp(out.scale_amp(self.mix.to_sample()).to_signed_frame())
+    }
+
+    /// Current delay in seconds without the wow and flutter, which lags behind a new delay
+    /// while the head glides there.
+    pub fn delay(&self) -> f64 {
+        self.delay / self.sample_rate as f64
+    }
+
+    /// In seconds, the head glides there over the glide time.
+    pub fn set_delay(&mut self, delay: f64) {
+        self.target = delay * self.sample_rate as f64;
+        self.clamp_target();
+    }
+
+    /// Keeps the target delay far enough from both ends of the tape for the modulation and the
+    /// interpolation.
+    fn clamp_target(&mut self) {
+        let margin = self.wow_depth + self.flutter_depth + 2.0;
+        let longest = (TAPE_DELAY_MAX_DELAY * self.sample_rate as f64 - margin).max(margin);
+        self.target = self.target.clamp(margin, longest);
+    }
+
+    /// Time constant in seconds for reaching a new delay, 0 jumps straight there.
+    pub fn set_glide(&mut self, time: f64) {
+        self.glide = if time > 0.0 {
+            1.0 - (-1.0 / (time * self.sample_rate as f64)).exp()
+        } else {
+            1.0
+        };
+    }
+
+    /// How far in seconds the slow random wow moves the read position.
+    pub fn set_wow(&mut self, depth: f64) {
+        self.wow_depth = depth.max(0.0) * self.sample_rate as f64;
+        self.clamp_target();
+    }
+
+    pub fn set_wow_rate(&mut self, rate: f64) {
+        self.wow.set_rate(rate);
+    }
+
+    /// How far in seconds the fast flutter moves the read position.
+    pub fn set_flutter(&mut self, depth: f64) {
+        self.flutter_depth = depth.max(0.0) * self.sample_rate as f64;
+        self.clamp_target();
+    }
+
+    pub fn set_flutter_rate(&mut self, rate: f64) {
+        self.flutter.set_rate(rate);
+    }
+
+    /// Reseeds the wow, so it wanders the same way for the same seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.wow.set_seed(seed);
+    }
+
+    /// Gain in dB into the saturation. Quiet signals come out at the same level whatever the
+    /// drive, it only lowers the level where the tape starts to squash.
+    pub fn set_drive(&mut self, drive: f64) {
+        self.drive = 10f64.powf(drive / 20.0);
+    }
+
+    /// Clamped to between 0 and `TAPE_DELAY_MAX_FEEDBACK`.
+    pub fn set_feedback(&mut self, feedback: f64) {
+        self.feedback = feedback.clamp(0.0, TAPE_DELAY_MAX_FEEDBACK);
+    }
+
+    /// Highpass and lowpass frequencies in Hz of the filter in the feedback loop.
+    pub fn set_bandwidth(&mut self, highpass: f64, lowpass: f64) {
+        let q = std::f64::consts::FRAC_1_SQRT_2;
+        self.highpass
+            .set_coefficients(BiquadCoefficients::highpass(highpass, q, self.sample_rate));
+        self.lowpass
+            .set_coefficients(BiquadCoefficients::lowpass(lowpass, q, self.sample_rate));
+    }
+
+    /// Between 0 for only the input and 1 for only the repeats.
+    pub fn set_mix(&mut self, mix: f64) {
+        self.mix = mix.clamp(0.0, 1.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::tests::measure_frequency;
+    use approx::assert_relative_eq;
+
+    fn steady_tape(delay: f64) -> TapeDelay<f64> {
+        let mut tape = TapeDelay::new(delay, 48000);
+        tape.set_wow(0.0);
+        tape.set_flutter(0.0);
+        tape.set_mix(1.0);
+        tape
+    }
+
+    #[test]
+    pub fn repeats_get_darker() {
+        let mut tape = steady_tape(0.01);
+        tape.set_feedback(0.8);
+
+        // Quiet enough for the saturation to leave it alone.
+        let out: Vec<f64> = (0..480 * 6)
+            .map(|n| tape.tick(if n == 0 { 0.01 } else { 0.0 }))
+            .collect();
+
+        assert_relative_eq!(out[480], 0.01, epsilon = 1e-6);
+        assert!(out[..480].iter().all(|o| *o == 0.0));
+
+        // How much of each repeat is in the changes from sample to sample, the highs.
+        let brightness: Vec<f64> = out
+            .chunks(480)
+            .skip(1)
+            .map(|repeat| {
+                let energy: f64 = repeat.iter().map(|x| x * x).sum();
+                let highs: f64 = repeat.windows(2).map(|w| (w[1] - w[0]).powi(2)).sum();
+                highs / energy
+            })
+            .collect();
+        for pair in brightness.windows(2) {
+            assert!(pair[1] < pair[0]);
+        }
+    }
+
+    #[test]
+    pub fn runs_at_the_longest_delay() {
+        let mut tape = TapeDelay::<f64>::new(TAPE_DELAY_MAX_DELAY, 48000);
+        assert!(tape.delay() < TAPE_DELAY_MAX_DELAY);
+
+        // Deeper modulation pulls the delay in further, so the read position still fits.
+        let w = 2.0 * std::f64::consts::PI * 300.0 / 48000.0;
+        for n in 0..3 * 48000 {
+            if n == 48000 {
+                tape.set_wow(0.01);
+                tape.set_flutter(0.001);
+            }
+            let out = tape.tick((w * n as f64).sin());
+            assert!(out.abs() < 2.0);
+        }
+        assert!(tape.delay() < TAPE_DELAY_MAX_DELAY - 0.011);
+    }
+
+    #[test]
+    pub fn saturation_holds_runaway_feedback() {
+        let mut tape = steady_tape(0.005);
+        tape.set_feedback(TAPE_DELAY_MAX_FEEDBACK);
+        tape.set_bandwidth(20.0, 20000.0);
+        tape.set_drive(6.0);
+
+        let w = 2.0 * std::f64::consts::PI * 200.0 / 48000.0;
+        let mut loudest = 0.0f64;
+        for n in 0..48000 * 2 {
+            let x = if n < 240 {
+                0.01 * (w * n as f64).sin()
+            } else {
+                0.0
+            };
+            let out = tape.tick(x).abs();
+            if n >= 48000 {
+                loudest = loudest.max(out);
+            }
+        }
+        // It has built up from the quiet input, but no further than the saturation allows.
+        let ceiling = 10f64.powf(-6.0 / 20.0);
+        assert!(loudest > 0.1);
+        assert!(loudest < ceiling * 1.3);
+    }
+
+    #[test]
+    pub fn delay_changes_glide() {
+        let mut tape = steady_tape(0.1);
+        tape.set_feedback(0.0);
+        tape.set_glide(0.1);
+
+        let w = 2.0 * std::f64::consts::PI * 1000.0 / 48000.0;
+        let mut out = vec![];
+        for n in 0..48000 {
+            if n == 9600 {
+                tape.set_delay(0.12);
+            }
+            out.push(tape.tick(0.1 * (w * n as f64).sin()));
+        }
+
+        // Before the change and once it has settled the pitch is untouched.
+        assert_relative_eq!(
+            measure_frequency(&out, 5000, 9600, 48000),
+            1000.0,
+            max_relative = 1e-4
+        );
+        assert_relative_eq!(
+            measure_frequency(&out, 40000, 47999, 48000),
+            1000.0,
+            max_relative = 1e-3
+        );
+        assert_relative_eq!(tape.delay(), 0.12, max_relative = 1e-3);
+
+        // While the head moves away the pitch drops by how fast the delay grows, which starts
+        // out at a fifth of a sample per sample here, without any jump in the output.
+        let start = measure_frequency(&out, 9600, 10080, 48000);
+        assert!(start > 780.0 && start < 850.0);
+        let steps = out[9600..20000].windows(2).map(|p| (p[1] - p[0]).abs());
+        assert!(steps.fold(0.0, f64::max) < 0.1 * w * 1.01);
+    }
+
+    #[test]
+    pub fn wow_and_flutter_bend_pitch() {
+        let run = |seed: u64| {
+            let mut tape = TapeDelay::<f64>::new(0.05, 48000);
+            tape.set_mix(1.0);
+            tape.set_feedback(0.0);
+            tape.set_seed(seed);
+            let w = 2.0 * std::f64::consts::PI * 1000.0 / 48000.0;
+            (0..48000)
+                .map(|n| tape.tick(0.1 * (w * n as f64).sin()))
+                .collect::<Vec<f64>>()
+        };
+
+        let out = run(1);
+        assert_eq!(out, run(1));
+        assert_ne!(out, run(2));
+
+        let pitches: Vec<f64> = (1..20)
+            .map(|i| measure_frequency(&out, i * 2400, i * 2400 + 1200, 48000))
+            .collect();
+        let lowest = pitches.iter().cloned().fold(f64::INFINITY, f64::min);
+        let highest = pitches.iter().cloned().fold(0.0, f64::max);
+        assert!(highest - lowest > 1.0);
+        assert!(highest - lowest < 50.0);
+    }
+}
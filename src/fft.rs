@@ -0,0 +1,163 @@
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    /// e^(i * angle)
+    pub fn from_angle(angle: f64) -> Self {
+        Complex::new(angle.cos(), angle.sin())
+    }
+
+    pub fn norm(self) -> f64 {
+        (self.re * self.re + self.im * self.im).sqrt()
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// In place radix-2 FFT. The length must be a power of two.
+///
+/// The inverse transform is scaled by 1/N, so a forward and inverse transform give back the input.
+pub struct Fft {
+    size: usize,
+    twiddles: Vec<Complex>,
+}
+
+impl Fft {
+    pub fn new(size: usize) -> Self {
+        assert!(size.is_power_of_two());
+
+        Fft {
+            size,
+            twiddles: (0..size / 2)
+                .map(|k| Complex::from_angle(-2.0 * std::f64::consts::PI * k as f64 / size as f64))
+                .collect(),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn forward(&self, data: &mut [Complex]) {
+        self.transform(data, false);
+    }
+
+    pub fn inverse(&self, data: &mut [Complex]) {
+        self.transform(data, true);
+
+        let scale = 1.0 / self.size as f64;
+        for d in data.iter_mut() {
+            d.re *= scale;
+            d.im *= scale;
+        }
+    }
+
+    fn transform(&self, data: &mut [Complex], inverse: bool) {
+        assert_eq!(data.len(), self.size);
+        let n = self.size;
+
+        // Bit reversal permutation
+        let mut j = 0;
+        for i in 1..n {
+            let mut bit = n >> 1;
+            while j & bit != 0 {
+                j ^= bit;
+                bit >>= 1;
+            }
+            j |= bit;
+            if i < j {
+                data.swap(i, j);
+            }
+        }
+
+        let mut length = 2;
+        while length <= n {
+            let step = n / length;
+            for start in (0..n).step_by(length) {
+                for k in 0..length / 2 {
+                    let mut w = self.twiddles[k * step];
+                    if inverse {
+                        w.im = -w.im;
+                    }
+                    let a = data[start + k];
+                    let b = data[start + k + length / 2] * w;
+                    data[start + k] = a + b;
+                    data[start + k + length / 2] = a - b;
+                }
+            }
+            length *= 2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn matches_dft() {
+        let n = 64;
+        let input: Vec<Complex> = (0..n)
+            .map(|i| Complex::new((i as f64 * 0.7).sin(), (i as f64 * 0.3).cos()))
+            .collect();
+
+        let mut data = input.clone();
+        let fft = Fft::new(n);
+        fft.forward(&mut data);
+
+        for (k, d) in data.iter().enumerate() {
+            let expected = input
+                .iter()
+                .enumerate()
+                .fold(Complex::default(), |sum, (i, x)| {
+                    sum + *x
+                        * Complex::from_angle(
+                            -2.0 * std::f64::consts::PI * (i * k) as f64 / n as f64,
+                        )
+                });
+            assert_relative_eq!(d.re, expected.re, epsilon = 1e-9);
+            assert_relative_eq!(d.im, expected.im, epsilon = 1e-9);
+        }
+
+        fft.inverse(&mut data);
+        for (d, x) in data.iter().zip(input.iter()) {
+            assert_relative_eq!(d.re, x.re, epsilon = 1e-12);
+            assert_relative_eq!(d.im, x.im, epsilon = 1e-12);
+        }
+    }
+}
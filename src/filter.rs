@@ -16,7 +16,7 @@ where
                 vec![F::EQUILIBRIUM; coefficients.len()],
                 coefficients.len() - 1,
             ),
-            coefficients: coefficients,
+            coefficients,
         }
     }
 
@@ -28,6 +28,11 @@ where
         self.coefficients.as_slice()
     }
 
+    /// Forgets past inputs, keeping the coefficients.
+    pub fn clear(&mut self) {
+        self.memory.clear();
+    }
+
     pub fn tick(&mut self, input: F) -> F {
         let mut output = input.scale_amp(self.coefficients[0].to_sample());
         for i in 1..self.coefficients.len() {
@@ -40,13 +45,389 @@ where
             );
         }
         self.memory.tick(input);
-        return output;
+        output
+    }
+}
+
+/// Coefficients of a second order IIR section, normalized so that a0 is 1.
+///
+/// The designs are the ones from the RBJ audio EQ cookbook.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BiquadCoefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl BiquadCoefficients {
+    fn from_unnormalized(b0: f64, b1: f64, b2: f64, a0: f64, a1: f64, a2: f64) -> Self {
+        BiquadCoefficients {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// Returns cos(w0) and alpha, which all the cookbook designs are built from.
+    fn cookbook_terms(frequency: f64, q: f64, sample_rate: usize) -> (f64, f64) {
+        assert!(frequency > 0.0 && frequency < sample_rate as f64 / 2.0);
+
+        let w0 = 2.0 * std::f64::consts::PI * frequency / sample_rate as f64;
+        (w0.cos(), w0.sin() / (2.0 * q))
+    }
+
+    pub fn lowpass(frequency: f64, q: f64, sample_rate: usize) -> Self {
+        let (cos_w0, alpha) = Self::cookbook_terms(frequency, q, sample_rate);
+
+        Self::from_unnormalized(
+            (1.0 - cos_w0) / 2.0,
+            1.0 - cos_w0,
+            (1.0 - cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn highpass(frequency: f64, q: f64, sample_rate: usize) -> Self {
+        let (cos_w0, alpha) = Self::cookbook_terms(frequency, q, sample_rate);
+
+        Self::from_unnormalized(
+            (1.0 + cos_w0) / 2.0,
+            -(1.0 + cos_w0),
+            (1.0 + cos_w0) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+
+    /// Bandpass with a peak gain of 0 dB at the centre frequency.
+    pub fn bandpass(frequency: f64, q: f64, sample_rate: usize) -> Self {
+        let (cos_w0, alpha) = Self::cookbook_terms(frequency, q, sample_rate);
+
+        Self::from_unnormalized(alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos_w0, 1.0 - alpha)
+    }
+
+    pub fn allpass(frequency: f64, q: f64, sample_rate: usize) -> Self {
+        let (cos_w0, alpha) = Self::cookbook_terms(frequency, q, sample_rate);
+
+        Self::from_unnormalized(
+            1.0 - alpha,
+            -2.0 * cos_w0,
+            1.0 + alpha,
+            1.0 + alpha,
+            -2.0 * cos_w0,
+            1.0 - alpha,
+        )
+    }
+}
+
+/// Second order IIR filter in transposed direct form II.
+pub struct Biquad<F> {
+    coefficients: BiquadCoefficients,
+    s1: F,
+    s2: F,
+}
+
+impl<F> Biquad<F>
+where
+    F: dasp::Frame,
+{
+    pub fn new(coefficients: BiquadCoefficients) -> Biquad<F> {
+        Biquad {
+            coefficients,
+            s1: F::EQUILIBRIUM,
+            s2: F::EQUILIBRIUM,
+        }
+    }
+
+    pub fn get_coefficients(&self) -> BiquadCoefficients {
+        self.coefficients
+    }
+
+    /// Replaces the coefficients but keeps the state, so the filter can be swept while running.
+    pub fn set_coefficients(&mut self, coefficients: BiquadCoefficients) {
+        self.coefficients = coefficients;
+    }
+
+    pub fn tick(&mut self, input: F) -> F {
+        let c = self.coefficients;
+
+        let output = input
+            .scale_amp(c.b0.to_sample())
+            .add_amp(self.s1.to_signed_frame());
+
+        self.s1 = input
+            .scale_amp(c.b1.to_sample())
+            .add_amp(output.scale_amp((-c.a1).to_sample()).to_signed_frame())
+            .add_amp(self.s2.to_signed_frame());
+        self.s2 = input
+            .scale_amp(c.b2.to_sample())
+            .add_amp(output.scale_amp((-c.a2).to_sample()).to_signed_frame());
+
+        output
+    }
+}
+
+/// 4th order Linkwitz-Riley section, made from two identical Butterworth biquads in series.
+pub struct LinkwitzRiley<F> {
+    first: Biquad<F>,
+    second: Biquad<F>,
+}
+
+impl<F> LinkwitzRiley<F>
+where
+    F: dasp::Frame,
+{
+    pub fn lowpass(frequency: f64, sample_rate: usize) -> Self {
+        Self::from_butterworth(BiquadCoefficients::lowpass(
+            frequency,
+            std::f64::consts::FRAC_1_SQRT_2,
+            sample_rate,
+        ))
+    }
+
+    pub fn highpass(frequency: f64, sample_rate: usize) -> Self {
+        Self::from_butterworth(BiquadCoefficients::highpass(
+            frequency,
+            std::f64::consts::FRAC_1_SQRT_2,
+            sample_rate,
+        ))
+    }
+
+    fn from_butterworth(coefficients: BiquadCoefficients) -> Self {
+        LinkwitzRiley {
+            first: Biquad::new(coefficients),
+            second: Biquad::new(coefficients),
+        }
+    }
+
+    pub fn tick(&mut self, input: F) -> F {
+        self.second.tick(self.first.tick(input))
+    }
+}
+
+/// Splits a signal into bands using 4th order Linkwitz-Riley filters.
+///
+/// The bands are split off one at a time from the bottom, and every band gets allpass
+/// compensation for the crossovers above it, so the bands always sum to an allpass response.
+pub struct Crossover<F> {
+    lowpasses: Vec<LinkwitzRiley<F>>,
+    highpasses: Vec<LinkwitzRiley<F>>,
+    // compensation[b] are the allpasses matching the crossovers band b is not split by.
+    compensation: Vec<Vec<Biquad<F>>>,
+    bands: Vec<F>,
+}
+
+impl<F> Crossover<F>
+where
+    F: dasp::Frame,
+{
+    /// Creates a crossover with `frequencies.len() + 1` bands. The frequencies must be increasing.
+    pub fn new(frequencies: &[f64], sample_rate: usize) -> Crossover<F> {
+        assert!(!frequencies.is_empty());
+        assert!(frequencies.windows(2).all(|w| w[0] < w[1]));
+
+        let band_count = frequencies.len() + 1;
+
+        // The sum of a Linkwitz-Riley lowpass and highpass is a Butterworth Q allpass.
+        let compensation = (0..band_count)
+            .map(|band| {
+                frequencies
+                    .iter()
+                    .skip(band + 1)
+                    .map(|f| {
+                        Biquad::new(BiquadCoefficients::allpass(
+                            *f,
+                            std::f64::consts::FRAC_1_SQRT_2,
+                            sample_rate,
+                        ))
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Crossover {
+            lowpasses: frequencies
+                .iter()
+                .map(|f| LinkwitzRiley::lowpass(*f, sample_rate))
+                .collect(),
+            highpasses: frequencies
+                .iter()
+                .map(|f| LinkwitzRiley::highpass(*f, sample_rate))
+                .collect(),
+            compensation,
+            bands: vec![F::EQUILIBRIUM; band_count],
+        }
+    }
+
+    pub fn band_count(&self) -> usize {
+        self.bands.len()
+    }
+
+    /// Processes one frame and returns the bands, lowest first.
+    pub fn tick(&mut self, input: F) -> &[F] {
+        let mut rest = input;
+        for i in 0..self.lowpasses.len() {
+            self.bands[i] = self.lowpasses[i].tick(rest);
+            rest = self.highpasses[i].tick(rest);
+        }
+        let last = self.bands.len() - 1;
+        self.bands[last] = rest;
+
+        for (band, allpasses) in self.bands.iter_mut().zip(self.compensation.iter_mut()) {
+            for allpass in allpasses.iter_mut() {
+                *band = allpass.tick(*band);
+            }
+        }
+
+        &self.bands
+    }
+}
+
+/// Resamples a signal with windowed sinc interpolation.
+///
+/// The kernel cuts off at the lower of the two Nyquist frequencies, so downsampling does not alias.
+pub fn resample(input: &[f64], from_rate: usize, to_rate: usize) -> Vec<f64> {
+    // Zero crossings of the sinc on each side of the kernel.
+    const ZERO_CROSSINGS: f64 = 16.0;
+
+    let ratio = to_rate as f64 / from_rate as f64;
+    let cutoff = ratio.min(1.0);
+    let half_width = ZERO_CROSSINGS / cutoff;
+    let length = (input.len() as f64 * ratio).ceil() as usize;
+
+    (0..length)
+        .map(|n| {
+            let t = n as f64 / ratio;
+            let first = (t - half_width).ceil().max(0.0) as usize;
+            let last = ((t + half_width).floor() as usize).min(input.len() - 1);
+
+            (first..=last)
+                .map(|i| {
+                    let x = t - i as f64;
+                    let sinc = if x == 0.0 {
+                        1.0
+                    } else {
+                        let a = std::f64::consts::PI * cutoff * x;
+                        a.sin() / a
+                    };
+                    let window = 0.5 * (1.0 + (std::f64::consts::PI * x / half_width).cos());
+                    input[i] * cutoff * sinc * window
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Taps of each phase of the oversampling filters, on each side of the centre.
+const OVERSAMPLER_HALF_TAPS: usize = 16;
+
+/// Polyphase interpolator and decimator for running a nonlinearity at a multiple of the sample rate.
+///
+/// Both sides use the same Blackman windowed sinc lowpass, cut off a little below the original
+/// Nyquist frequency. Upsampling only evaluates the phase of the kernel that lines up with each
+/// input sample, and downsampling only computes the outputs that are kept.
+pub struct Oversampler {
+    factor: usize,
+    kernel: Vec<f64>,
+    // Newest first, at the original and the oversampled rate.
+    input: Vec<f64>,
+    output: Vec<f64>,
+    upsampled: Vec<f64>,
+}
+
+impl Oversampler {
+    pub fn new(factor: usize) -> Self {
+        assert!(factor >= 1);
+
+        let length = 2 * OVERSAMPLER_HALF_TAPS * factor + 1;
+        let centre = (length / 2) as f64;
+        let cutoff = 0.42 / factor as f64;
+        let mut kernel: Vec<f64> = (0..length)
+            .map(|i| {
+                let x = i as f64 - centre;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    let a = 2.0 * std::f64::consts::PI * cutoff * x;
+                    a.sin() / a
+                };
+                let phase = 2.0 * std::f64::consts::PI * i as f64 / (length - 1) as f64;
+                let window = 0.42 - 0.5 * phase.cos() + 0.08 * (2.0 * phase).cos();
+                sinc * window
+            })
+            .collect();
+        let sum: f64 = kernel.iter().sum();
+        for k in kernel.iter_mut() {
+            *k /= sum;
+        }
+
+        Oversampler {
+            factor,
+            kernel,
+            input: vec![0.0; 2 * OVERSAMPLER_HALF_TAPS + 1],
+            output: vec![0.0; length + factor - 1],
+            upsampled: vec![0.0; factor],
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    /// Delay in samples at the original rate of going up and back down.
+    pub fn latency(&self) -> usize {
+        2 * OVERSAMPLER_HALF_TAPS
+    }
+
+    /// Takes one sample and returns `factor` samples at the higher rate.
+    pub fn upsample(&mut self, input: f64) -> &[f64] {
+        self.input.rotate_right(1);
+        self.input[0] = input;
+
+        for (phase, out) in self.upsampled.iter_mut().enumerate() {
+            *out = self
+                .kernel
+                .iter()
+                .skip(phase)
+                .step_by(self.factor)
+                .zip(self.input.iter())
+                .map(|(h, x)| h * x)
+                .sum::<f64>()
+                * self.factor as f64;
+        }
+
+        &self.upsampled
+    }
+
+    /// Takes `factor` samples at the higher rate and returns one at the original rate.
+    pub fn downsample(&mut self, input: &[f64]) -> f64 {
+        assert_eq!(input.len(), self.factor);
+
+        self.output.rotate_right(self.factor);
+        for (o, x) in self.output.iter_mut().zip(input.iter().rev()) {
+            *o = *x;
+        }
+
+        // The output lines up with the first of the new samples.
+        self.kernel
+            .iter()
+            .zip(self.output.iter().skip(self.factor - 1))
+            .map(|(h, x)| h * x)
+            .sum()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::filter::*;
+    use approx::assert_relative_eq;
     #[test]
     pub fn fir_impulse_response() {
         //For a fir filter the impules response should equal the coefficients.
@@ -61,4 +442,143 @@ mod tests {
             assert_eq!(*c, o);
         }
     }
+
+    /// Magnitude in dB of the impulse response at the given frequency.
+    fn magnitude_db(impulse_response: &[f64], frequency: f64, sample_rate: usize) -> f64 {
+        let w = 2.0 * std::f64::consts::PI * frequency / sample_rate as f64;
+        let (re, im) = impulse_response
+            .iter()
+            .enumerate()
+            .fold((0.0, 0.0), |(re, im), (n, h)| {
+                (re + h * (w * n as f64).cos(), im - h * (w * n as f64).sin())
+            });
+
+        20.0 * (re * re + im * im).sqrt().log10()
+    }
+
+    fn crossover_sum_response(frequencies: &[f64], sample_rate: usize) -> Vec<f64> {
+        let mut crossover = Crossover::<f64>::new(frequencies, sample_rate);
+        assert_eq!(crossover.band_count(), frequencies.len() + 1);
+
+        (0..16384)
+            .map(|n| {
+                let input = if n == 0 { 1.0 } else { 0.0 };
+                crossover.tick(input).iter().sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn crossover_sums_flat() {
+        let sample_rate = 48000;
+        for frequencies in [vec![1000.0], vec![120.0, 800.0, 4000.0, 12000.0]].iter() {
+            let response = crossover_sum_response(frequencies, sample_rate);
+
+            let mut f = 20.0;
+            while f < 20000.0 {
+                assert!(magnitude_db(&response, f, sample_rate).abs() < 0.01);
+                f *= 1.25;
+            }
+        }
+    }
+
+    #[test]
+    pub fn bandpass_peak() {
+        let sample_rate = 48000;
+        let mut bandpass =
+            Biquad::<f64>::new(BiquadCoefficients::bandpass(1000.0, 4.0, sample_rate));
+        let response: Vec<f64> = (0..16384)
+            .map(|n| bandpass.tick(if n == 0 { 1.0 } else { 0.0 }))
+            .collect();
+
+        assert_relative_eq!(
+            magnitude_db(&response, 1000.0, sample_rate),
+            0.0,
+            epsilon = 1e-6
+        );
+        assert!(magnitude_db(&response, 250.0, sample_rate) < -20.0);
+        assert!(magnitude_db(&response, 4000.0, sample_rate) < -20.0);
+    }
+
+    #[test]
+    pub fn linkwitz_riley_crossover_point() {
+        // Both halves of a Linkwitz-Riley crossover are 6 dB down at the crossover frequency.
+        let sample_rate = 48000;
+        let mut lowpass = LinkwitzRiley::<f64>::lowpass(1000.0, sample_rate);
+        let mut highpass = LinkwitzRiley::<f64>::highpass(1000.0, sample_rate);
+
+        let mut low = vec![];
+        let mut high = vec![];
+        for n in 0..16384 {
+            let input = if n == 0 { 1.0 } else { 0.0 };
+            low.push(lowpass.tick(input));
+            high.push(highpass.tick(input));
+        }
+
+        let expected = 20.0 * 0.5f64.log10();
+        assert_relative_eq!(
+            magnitude_db(&low, 1000.0, sample_rate),
+            expected,
+            epsilon = 1e-6
+        );
+        assert_relative_eq!(
+            magnitude_db(&high, 1000.0, sample_rate),
+            expected,
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    pub fn resample_sine() {
+        let w = 2.0 * std::f64::consts::PI * 1000.0;
+        let input: Vec<f64> = (0..4410).map(|n| (w * n as f64 / 44100.0).sin()).collect();
+
+        for to_rate in [48000, 32000].iter() {
+            let output = resample(&input, 44100, *to_rate);
+            assert_eq!(output.len(), input.len() * to_rate / 44100);
+
+            // Away from the ends, where the kernel runs out of input
+            for (n, v) in output.iter().enumerate().skip(100).take(output.len() - 200) {
+                let expected = (w * n as f64 / *to_rate as f64).sin();
+                assert_relative_eq!(*v, expected, epsilon = 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    pub fn oversampler_round_trip() {
+        let w = 2.0 * std::f64::consts::PI * 0.05;
+        for factor in [1, 2, 4, 8].iter() {
+            let mut oversampler = Oversampler::new(*factor);
+            let latency = oversampler.latency();
+
+            for n in 0..500 {
+                let up = oversampler.upsample((w * n as f64).sin()).to_vec();
+                let out = oversampler.downsample(&up);
+                if n >= 2 * latency {
+                    let expected = (w * (n - latency) as f64).sin();
+                    assert_relative_eq!(out, expected, epsilon = 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn oversampler_interpolates() {
+        // Upsampling a slow sine gives the same sine at the higher rate, delayed by half the latency.
+        let factor = 4;
+        let mut oversampler = Oversampler::new(factor);
+        let w = 2.0 * std::f64::consts::PI * 0.02;
+        let delay = oversampler.latency() / 2;
+
+        for n in 0..300 {
+            let up = oversampler.upsample((w * n as f64).sin()).to_vec();
+            if n >= 2 * delay {
+                for (k, u) in up.iter().enumerate() {
+                    let t = n as f64 + k as f64 / factor as f64 - delay as f64;
+                    assert_relative_eq!(*u, (w * t).sin(), epsilon = 1e-3);
+                }
+            }
+        }
+    }
 }
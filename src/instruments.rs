@@ -5,6 +5,16 @@ use dasp::frame::Mono;
 use dasp::{Frame, Sample};
 use dasp_signal::{Noise, Signal};
 
+/// Coefficients of the 3-tap loop filter of a string with the given period and decay (time to
+/// die down by 60 dB) in seconds. Their sum is the loss per period.
+pub(crate) fn string_loop_filter(period: f64, decay: f64, brightness: f64) -> [f64; 3] {
+    // See PASP §9.1.2
+    let rho = (-6.91 * period / decay).exp();
+    let g0 = rho * (1.0 + brightness) / 2.0;
+    let g1 = rho * (1.0 - brightness) / 4.0;
+    [g1, g0, g1]
+}
+
 pub struct PluckedString<T> {
     string_delay: DelayLineFracLin<Vec<Mono<T>>>,
     string_filter: FIRFilter<Mono<T>>,
@@ -18,6 +28,7 @@ where
     T: dasp::Sample,
     T: dasp::sample::FromSample<f64>,
 {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> PluckedString<T> {
         //TODO calculate length based on min/max frequency. maybe random seed?
         PluckedString {
@@ -41,15 +52,9 @@ where
         // - 1.0 to compensate for delay introduced by filter
         self.string_delay.set_delay(delay - 1.0);
 
-        // See PASP §9.1.2
-        let rho = (-6.91 * period / self.sustain).exp();
-        let g0 = rho * (1.0 + self.brightness) / 2.0;
-        let g1 = rho * (1.0 - self.brightness) / 4.0;
-
-        let filter_coefs = self.string_filter.get_mut_coefficients();
-        filter_coefs[0] = g1;
-        filter_coefs[1] = g0;
-        filter_coefs[2] = g1;
+        self.string_filter
+            .get_mut_coefficients()
+            .copy_from_slice(&string_loop_filter(period, self.sustain, self.brightness));
 
         for _ in 0..(delay.ceil()) as usize {
             self.string_delay.tick([self.pick_noise.next().to_sample()]);
@@ -59,6 +64,6 @@ where
     pub fn tick(&mut self) -> dasp::frame::Mono<T> {
         let out = self.string_filter.tick(self.string_delay.tap_output());
         self.string_delay.tick(out);
-        return out;
+        out
     }
 }
@@ -0,0 +1,227 @@
+use rand::distributions::{Distribution, Uniform};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+const SINE_TABLE_SIZE: usize = 512;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    /// Rising ramp from -1 to 1.
+    Saw,
+    Square,
+    /// A new random value at the start of every cycle, held until the next one.
+    SampleAndHold,
+    /// Random values like `SampleAndHold`, but with a cosine glide between them.
+    SmoothRandom,
+}
+
+/// Low frequency oscillator producing values in [-1, 1].
+///
+/// The phase is kept as an accumulator in [0, 1), so unlike computing sin(rate * time) it does not
+/// lose precision however long it runs.
+pub struct Lfo {
+    waveform: Waveform,
+    phase: f64,
+    phase_offset: f64,
+    increment: f64,
+    sample_rate: f64,
+    sine_table: Vec<f64>,
+    rng: StdRng,
+    random_previous: f64,
+    random_next: f64,
+}
+
+impl Lfo {
+    pub fn new(waveform: Waveform, rate: f64, sample_rate: usize) -> Self {
+        let mut lfo = Lfo {
+            waveform,
+            phase: 0.0,
+            phase_offset: 0.0,
+            increment: rate / sample_rate as f64,
+            sample_rate: sample_rate as f64,
+            sine_table: (0..=SINE_TABLE_SIZE)
+                .map(|i| (2.0 * std::f64::consts::PI * i as f64 / SINE_TABLE_SIZE as f64).sin())
+                .collect(),
+            rng: StdRng::seed_from_u64(0),
+            random_previous: 0.0,
+            random_next: 0.0,
+        };
+        lfo.set_seed(0);
+        lfo
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    pub fn get_waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    /// Rate in Hz.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.increment = rate / self.sample_rate;
+    }
+
+    pub fn get_rate(&self) -> f64 {
+        self.increment * self.sample_rate
+    }
+
+    /// Offset added to the phase, in cycles. 0.25 shifts the output by a quarter period.
+    pub fn set_phase_offset(&mut self, offset: f64) {
+        self.phase_offset = offset.rem_euclid(1.0);
+    }
+
+    pub fn get_phase_offset(&self) -> f64 {
+        self.phase_offset
+    }
+
+    /// The current phase in cycles including the offset, in [0, 1).
+    pub fn get_phase(&self) -> f64 {
+        (self.phase + self.phase_offset).fract()
+    }
+
+    /// Restarts the oscillator at the beginning of its cycle. The phase offset is kept.
+    pub fn reset(&mut self) {
+        self.phase = 0.0;
+    }
+
+    /// Reseeds the random waveforms, so they produce the same sequence for the same seed.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+        self.random_previous = self.random_value();
+        self.random_next = self.random_value();
+    }
+
+    fn random_value(&mut self) -> f64 {
+        Uniform::new_inclusive(-1.0, 1.0).sample(&mut self.rng)
+    }
+
+    fn sine(&self, phase: f64) -> f64 {
+        let position = phase * SINE_TABLE_SIZE as f64;
+        let index = position as usize;
+        let fraction = position - index as f64;
+
+        self.sine_table[index] * (1.0 - fraction) + self.sine_table[index + 1] * fraction
+    }
+
+    /// The output at the current phase, without advancing.
+    pub fn value(&self) -> f64 {
+        let phase = self.get_phase();
+
+        match self.waveform {
+            Waveform::Sine => self.sine(phase),
+            Waveform::Triangle => 1.0 - 4.0 * ((phase + 0.25).fract() - 0.5).abs(),
+            Waveform::Saw => 2.0 * phase - 1.0,
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::SampleAndHold => self.random_previous,
+            Waveform::SmoothRandom => {
+                let t = (1.0 - (std::f64::consts::PI * phase).cos()) / 2.0;
+                self.random_previous * (1.0 - t) + self.random_next * t
+            }
+        }
+    }
+
+    /// Returns the output at the current phase and advances one sample.
+    pub fn tick(&mut self) -> f64 {
+        let out = self.value();
+
+        let before = self.get_phase();
+        self.phase = (self.phase + self.increment).rem_euclid(1.0);
+        if self.get_phase() < before {
+            self.random_previous = self.random_next;
+            self.random_next = self.random_value();
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn sine_matches_sin() {
+        let sample_rate = 48000;
+        let rate = 3.7;
+        let mut lfo = Lfo::new(Waveform::Sine, rate, sample_rate);
+
+        for n in 0..100000 {
+            let expected =
+                (2.0 * std::f64::consts::PI * rate * n as f64 / sample_rate as f64).sin();
+            assert_relative_eq!(lfo.tick(), expected, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    pub fn basic_shapes() {
+        // A rate of a quarter of the sample rate visits the phases 0, 0.25, 0.5 and 0.75.
+        let expected = [
+            (Waveform::Triangle, [0.0, 1.0, 0.0, -1.0]),
+            (Waveform::Saw, [-1.0, -0.5, 0.0, 0.5]),
+            (Waveform::Square, [1.0, 1.0, -1.0, -1.0]),
+        ];
+
+        for (waveform, values) in expected.iter() {
+            let mut lfo = Lfo::new(*waveform, 1.0, 4);
+            for _ in 0..3 {
+                for v in values.iter() {
+                    assert_relative_eq!(lfo.tick(), *v, epsilon = 1e-12);
+                }
+            }
+        }
+    }
+
+    #[test]
+    pub fn phase_offset_and_reset() {
+        let mut lfo = Lfo::new(Waveform::Saw, 1.0, 4);
+        lfo.set_phase_offset(0.5);
+        assert_relative_eq!(lfo.tick(), 0.0);
+        assert_relative_eq!(lfo.tick(), 0.5);
+        assert_relative_eq!(lfo.tick(), -1.0);
+
+        lfo.reset();
+        assert_relative_eq!(lfo.tick(), 0.0);
+    }
+
+    #[test]
+    pub fn random_shapes_are_seeded() {
+        for waveform in [Waveform::SampleAndHold, Waveform::SmoothRandom].iter() {
+            let mut a = Lfo::new(*waveform, 10.0, 1000);
+            let mut b = Lfo::new(*waveform, 10.0, 1000);
+            a.set_seed(42);
+            b.set_seed(42);
+
+            for _ in 0..10000 {
+                let v = a.tick();
+                assert!((-1.0..=1.0).contains(&v));
+                assert_eq!(v, b.tick());
+            }
+        }
+    }
+
+    #[test]
+    pub fn sample_and_hold_holds_for_a_cycle() {
+        // 128 samples per cycle, so the phase increment is exact.
+        let mut lfo = Lfo::new(Waveform::SampleAndHold, 1000.0 / 128.0, 1000);
+
+        let mut previous = lfo.tick();
+        for n in 1..1000 {
+            let v = lfo.tick();
+            if n % 128 != 0 {
+                assert_eq!(v, previous);
+            }
+            previous = v;
+        }
+    }
+}
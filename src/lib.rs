@@ -1,15 +1,20 @@
 pub mod delay_line;
 pub mod effects;
+pub mod fft;
 pub mod filter;
 pub mod instruments;
+pub mod lfo;
+pub mod room;
+pub mod wav;
 
 use std::sync::Arc;
 
-use vst::api::Events;
+use dasp::frame::Stereo;
+
+use vst::api::{Events, Supported};
 use vst::buffer::AudioBuffer;
 use vst::event::Event;
-use vst::plugin::{Category, Info, Plugin, PluginParameters};
-use vst::plugin_main;
+use vst::plugin::{CanDo, Category, Info, Plugin, PluginParameters};
 use vst::util::ParameterTransfer;
 
 struct DwsParams {
@@ -21,7 +26,13 @@ impl DwsParams {
         match index {
             0 => value / 10.0,
             1 => value / 0.01,
-            2 => value,
+            2 => (value + 1.0) / 2.0,
+            3 => (value / effects::FLANGE_MAX_FEEDBACK as f32 + 1.0) / 2.0,
+            4 => value,
+            5 => value / 180.0,
+            6 => value,
+            7 => (value - 1.0) / (effects::CHORUS_MAX_VOICES - 1) as f32,
+            8 => value,
             _ => 0.0,
         }
     }
@@ -30,7 +41,13 @@ impl DwsParams {
         match index {
             0 => value * 10.0,
             1 => value * 0.01,
-            2 => value,
+            2 => value * 2.0 - 1.0,
+            3 => (value * 2.0 - 1.0) * effects::FLANGE_MAX_FEEDBACK as f32,
+            4 => value.round(),
+            5 => value * 180.0,
+            6 => value.round(),
+            7 => (value * (effects::CHORUS_MAX_VOICES - 1) as f32).round() + 1.0,
+            8 => value,
             _ => 0.0,
         }
     }
@@ -50,6 +67,12 @@ impl PluginParameters for DwsParams {
             0 => "rate".to_string(),
             1 => "amount".to_string(),
             2 => "depth".to_string(),
+            3 => "feedback".to_string(),
+            4 => "through zero".to_string(),
+            5 => "stereo spread".to_string(),
+            6 => "mode".to_string(),
+            7 => "chorus voices".to_string(),
+            8 => "chorus mix".to_string(),
             _ => "computer says no".to_string(),
         }
     }
@@ -59,6 +82,12 @@ impl PluginParameters for DwsParams {
             0 => "Hz".to_string(),
             1 => "s".to_string(),
             2 => "".to_string(),
+            3 => "".to_string(),
+            4 => "".to_string(),
+            5 => "deg".to_string(),
+            6 => "".to_string(),
+            7 => "".to_string(),
+            8 => "".to_string(),
             _ => "computer says no".to_string(),
         }
     }
@@ -68,7 +97,14 @@ impl PluginParameters for DwsParams {
     }
 
     fn get_parameter_text(&self, index: i32) -> String {
-        format!("{number:.5}", number = self.get_denorm_parameter(index))
+        match index {
+            4 if self.get_denorm_parameter(index) > 0.5 => "on".to_string(),
+            4 => "off".to_string(),
+            6 if self.get_denorm_parameter(index) > 0.5 => "chorus".to_string(),
+            6 => "flanger".to_string(),
+            7 => format!("{}", self.get_denorm_parameter(index)),
+            _ => format!("{number:.5}", number = self.get_denorm_parameter(index)),
+        }
     }
 
     fn set_parameter(&self, index: i32, value: f32) {
@@ -76,18 +112,31 @@ impl PluginParameters for DwsParams {
     }
 }
 
-struct Dws {
-    flange: effects::Flange<dasp::frame::Stereo<f32>>,
+pub struct Dws {
+    flange: effects::StereoFlange<f32>,
+    chorus: effects::Chorus<f32>,
+    chorus_mode: bool,
     params: Arc<DwsParams>,
 }
 
 impl Default for Dws {
     fn default() -> Dws {
+        let params = DwsParams {
+            param_transfer: ParameterTransfer::new(9),
+        };
+        // Start the host side out at the same settings as the flanger, with one chorus voice.
+        for (index, value) in [5.0, 0.001, 0.3, 0.0, 0.0, 0.0, 0.0, 1.0, 0.5]
+            .iter()
+            .enumerate()
+        {
+            params.set_denorm_parameter(index as i32, *value);
+        }
+
         Dws {
-            flange: effects::Flange::new(5.0, 0.001, 0.3, 48000),
-            params: std::sync::Arc::new(DwsParams {
-                param_transfer: ParameterTransfer::new(3),
-            }),
+            flange: effects::StereoFlange::new(5.0, 0.001, 0.3, 48000),
+            chorus: effects::Chorus::new(1, 48000),
+            chorus_mode: false,
+            params: std::sync::Arc::new(params),
         }
     }
 }
@@ -99,7 +148,7 @@ impl Plugin for Dws {
             unique_id: 84781384, // Used by hosts to differentiate between plugins.
             inputs: 2,
             outputs: 2,
-            parameters: 3,
+            parameters: 9,
 
             ..Default::default()
         }
@@ -112,30 +161,914 @@ impl Plugin for Dws {
     fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
         for (index, value) in self.params.param_transfer.iterate(true) {
             match index {
-                0 => self.flange.set_rate(value as f64),
-                1 => self.flange.set_amount(value as f64),
+                0 => {
+                    self.flange.set_rate(value as f64);
+                    self.chorus.set_rate(value as f64);
+                }
+                1 => {
+                    self.flange.set_amount(value as f64);
+                    self.chorus.set_depth(value as f64);
+                }
                 2 => self.flange.set_depth(value as f64),
+                3 => self.flange.set_feedback(value as f64),
+                4 => self.flange.set_through_zero(value > 0.5),
+                5 => self.flange.set_spread(value as f64),
+                6 => self.chorus_mode = value > 0.5,
+                7 => self.chorus.set_voice_count(value as usize),
+                8 => self.chorus.set_mix(value as f64),
+                _ => {}
+            }
+        }
+
+        let (inputs, mut outputs) = buffer.split();
+
+        let left_in = inputs.get(0).iter();
+        let right_in = inputs.get(1).iter();
+
+        let left_out = outputs.get_mut(0).iter_mut();
+        let right_out = outputs.get_mut(1).iter_mut();
+
+        for ((li, ri), (lo, ro)) in left_in.zip(right_in).zip(left_out.zip(right_out)) {
+            let o = if self.chorus_mode {
+                self.chorus.tick([*li, *ri])
+            } else {
+                self.flange.tick([*li, *ri])
+            };
+            *lo = o[0];
+            *ro = o[1];
+        }
+    }
+}
+
+struct ReverbParams {
+    param_transfer: ParameterTransfer,
+}
+
+impl ReverbParams {
+    fn normalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0..=5 => value,
+            _ => 0.0,
+        }
+    }
+
+    fn denormalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0..=4 => value,
+            5 => value.round(),
+            _ => 0.0,
+        }
+    }
+
+    fn get_denorm_parameter(&self, index: i32) -> f32 {
+        self.param_transfer.get_parameter(index as usize)
+    }
+
+    fn set_denorm_parameter(&self, index: i32, value: f32) {
+        self.param_transfer.set_parameter(index as usize, value);
+    }
+}
+
+impl PluginParameters for ReverbParams {
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "room size".to_string(),
+            1 => "damping".to_string(),
+            2 => "width".to_string(),
+            3 => "wet".to_string(),
+            4 => "dry".to_string(),
+            5 => "freeze".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            0..=5 => "".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        ReverbParams::normalize_parameter(index, self.get_denorm_parameter(index))
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            5 if self.get_denorm_parameter(index) > 0.5 => "on".to_string(),
+            5 => "off".to_string(),
+            _ => format!("{number:.3}", number = self.get_denorm_parameter(index)),
+        }
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        self.set_denorm_parameter(index, ReverbParams::denormalize_parameter(index, value));
+    }
+}
+
+pub struct VstReverb {
+    reverb: effects::Reverb<f32>,
+    params: Arc<ReverbParams>,
+}
+
+impl Default for VstReverb {
+    fn default() -> VstReverb {
+        let params = ReverbParams {
+            param_transfer: ParameterTransfer::new(6),
+        };
+        // Start the host side out at the same settings as the reverb.
+        for (index, value) in [0.5, 0.5, 1.0, 1.0 / 3.0, 0.0, 0.0].iter().enumerate() {
+            params.set_denorm_parameter(index as i32, *value);
+        }
+
+        VstReverb {
+            reverb: effects::Reverb::new(48000),
+            params: std::sync::Arc::new(params),
+        }
+    }
+}
+
+impl Plugin for VstReverb {
+    fn get_info(&self) -> Info {
+        Info {
+            name: "dws_reverb".to_string(),
+            unique_id: 84781385, // Used by hosts to differentiate between plugins.
+            inputs: 2,
+            outputs: 2,
+            parameters: 6,
+
+            ..Default::default()
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.reverb = effects::Reverb::new(rate as usize);
+        // Apply all parameters again to the new reverb.
+        for index in 0..6 {
+            self.params
+                .set_denorm_parameter(index, self.params.get_denorm_parameter(index));
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        self.params.clone()
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        for (index, value) in self.params.param_transfer.iterate(true) {
+            match index {
+                0 => self.reverb.set_room_size(value as f64),
+                1 => self.reverb.set_damping(value as f64),
+                2 => self.reverb.set_width(value as f64),
+                3 => self.reverb.set_wet(value as f64),
+                4 => self.reverb.set_dry(value as f64),
+                5 => self.reverb.set_freeze(value > 0.5),
+                _ => {}
+            }
+        }
+
+        let (inputs, mut outputs) = buffer.split();
+
+        let left_in = inputs.get(0).iter();
+        let right_in = inputs.get(1).iter();
+
+        let left_out = outputs.get_mut(0).iter_mut();
+        let right_out = outputs.get_mut(1).iter_mut();
+
+        for ((li, ri), (lo, ro)) in left_in.zip(right_in).zip(left_out.zip(right_out)) {
+            let o = self.reverb.tick([*li, *ri]);
+            *lo = o[0];
+            *ro = o[1];
+        }
+    }
+}
+
+/// MIDI controller switching the rotary speaker between slow and fast, the modulation wheel.
+const ROTARY_SPEED_CC: u8 = 1;
+
+struct RotaryParams {
+    param_transfer: ParameterTransfer,
+}
+
+impl RotaryParams {
+    fn normalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => value,
+            1 => value / 180.0,
+            2 | 3 => value,
+            _ => 0.0,
+        }
+    }
+
+    fn denormalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => value.round(),
+            1 => value * 180.0,
+            2 | 3 => value,
+            _ => 0.0,
+        }
+    }
+
+    fn get_denorm_parameter(&self, index: i32) -> f32 {
+        self.param_transfer.get_parameter(index as usize)
+    }
+
+    fn set_denorm_parameter(&self, index: i32, value: f32) {
+        self.param_transfer.set_parameter(index as usize, value);
+    }
+}
+
+impl PluginParameters for RotaryParams {
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "speed".to_string(),
+            1 => "mic spread".to_string(),
+            2 => "horn level".to_string(),
+            3 => "drum level".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            1 => "deg".to_string(),
+            0 | 2 | 3 => "".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        RotaryParams::normalize_parameter(index, self.get_denorm_parameter(index))
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            0 if self.get_denorm_parameter(index) > 0.5 => "fast".to_string(),
+            0 => "slow".to_string(),
+            _ => format!("{number:.3}", number = self.get_denorm_parameter(index)),
+        }
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        self.set_denorm_parameter(index, RotaryParams::denormalize_parameter(index, value));
+    }
+}
+
+pub struct VstRotary {
+    rotary: effects::Rotary<f32>,
+    params: Arc<RotaryParams>,
+}
+
+impl Default for VstRotary {
+    fn default() -> VstRotary {
+        let params = RotaryParams {
+            param_transfer: ParameterTransfer::new(4),
+        };
+        // Start the host side out at the same settings as the rotary.
+        for (index, value) in [0.0, 180.0, 1.0, 1.0].iter().enumerate() {
+            params.set_denorm_parameter(index as i32, *value);
+        }
+
+        VstRotary {
+            rotary: effects::Rotary::new(48000),
+            params: std::sync::Arc::new(params),
+        }
+    }
+}
+
+impl Plugin for VstRotary {
+    fn get_info(&self) -> Info {
+        Info {
+            name: "dws_rotary".to_string(),
+            unique_id: 84781386, // Used by hosts to differentiate between plugins.
+            inputs: 2,
+            outputs: 2,
+            midi_inputs: 1,
+            parameters: 4,
+
+            ..Default::default()
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.rotary = effects::Rotary::new(rate as usize);
+        // Apply all parameters again to the new rotary.
+        for index in 0..4 {
+            self.params
+                .set_denorm_parameter(index, self.params.get_denorm_parameter(index));
+        }
+    }
+
+    fn can_do(&self, can_do: CanDo) -> Supported {
+        match can_do {
+            CanDo::ReceiveMidiEvent => Supported::Yes,
+            _ => Supported::Maybe,
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        self.params.clone()
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        for (index, value) in self.params.param_transfer.iterate(true) {
+            match index {
+                0 => self.rotary.set_fast(value > 0.5),
+                1 => self.rotary.set_mic_spread(value as f64),
+                2 | 3 => self.rotary.set_levels(
+                    self.params.get_denorm_parameter(2) as f64,
+                    self.params.get_denorm_parameter(3) as f64,
+                ),
                 _ => {}
             }
         }
 
         let (inputs, mut outputs) = buffer.split();
 
-        let left_in = inputs.get(0).into_iter();
-        let right_in = inputs.get(1).into_iter();
+        let left_in = inputs.get(0).iter();
+        let right_in = inputs.get(1).iter();
 
-        let left_out = outputs.get_mut(0).into_iter();
-        let right_out = outputs.get_mut(1).into_iter();
+        let left_out = outputs.get_mut(0).iter_mut();
+        let right_out = outputs.get_mut(1).iter_mut();
 
         for ((li, ri), (lo, ro)) in left_in.zip(right_in).zip(left_out.zip(right_out)) {
-            let o = self.flange.tick([*li, *ri]);
+            let o = self.rotary.tick([*li, *ri]);
             *lo = o[0];
             *ro = o[1];
         }
     }
+
+    fn process_events(&mut self, events: &Events) {
+        for event in events.events() {
+            if let Event::Midi(ev) = event {
+                // Control change on any channel. Going through the parameter keeps the host in
+                // step with the speed.
+                if ev.data[0] & 0xF0 == 0xB0 && ev.data[1] == ROTARY_SPEED_CC {
+                    let fast = if ev.data[2] >= 64 { 1.0 } else { 0.0 };
+                    self.params.set_denorm_parameter(0, fast);
+                }
+            }
+        }
+    }
+}
+
+struct CompressorParams {
+    param_transfer: ParameterTransfer,
+}
+
+impl CompressorParams {
+    fn normalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => (value + 60.0) / 60.0,
+            1 => (value - 1.0) / 19.0,
+            2 => value / 24.0,
+            3 => value / 0.1,
+            4 => value,
+            5 => value / 24.0,
+            6 | 7 => value,
+            _ => 0.0,
+        }
+    }
+
+    fn denormalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => value * 60.0 - 60.0,
+            1 => value * 19.0 + 1.0,
+            2 => value * 24.0,
+            3 => value * 0.1,
+            4 => value,
+            5 => value * 24.0,
+            6 => value,
+            7 => value.round(),
+            _ => 0.0,
+        }
+    }
+
+    fn get_denorm_parameter(&self, index: i32) -> f32 {
+        self.param_transfer.get_parameter(index as usize)
+    }
+
+    fn set_denorm_parameter(&self, index: i32, value: f32) {
+        self.param_transfer.set_parameter(index as usize, value);
+    }
 }
 
-struct VstPluckedString {
+impl PluginParameters for CompressorParams {
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "threshold".to_string(),
+            1 => "ratio".to_string(),
+            2 => "knee".to_string(),
+            3 => "attack".to_string(),
+            4 => "release".to_string(),
+            5 => "makeup".to_string(),
+            6 => "stereo link".to_string(),
+            7 => "sidechain".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            0 | 2 | 5 => "dB".to_string(),
+            3 | 4 => "s".to_string(),
+            1 | 6 | 7 => "".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        CompressorParams::normalize_parameter(index, self.get_denorm_parameter(index))
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            7 if self.get_denorm_parameter(index) > 0.5 => "on".to_string(),
+            7 => "off".to_string(),
+            _ => format!("{number:.3}", number = self.get_denorm_parameter(index)),
+        }
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        self.set_denorm_parameter(index, CompressorParams::denormalize_parameter(index, value));
+    }
+}
+
+/// Compressor with a second input pair that can drive the detector instead of the main input.
+pub struct VstCompressor {
+    compressor: effects::dynamics::Compressor<f32>,
+    sidechain: bool,
+    params: Arc<CompressorParams>,
+}
+
+impl Default for VstCompressor {
+    fn default() -> VstCompressor {
+        let params = CompressorParams {
+            param_transfer: ParameterTransfer::new(8),
+        };
+        // Start the host side out at the same settings as the compressor.
+        for (index, value) in [-20.0, 4.0, 6.0, 0.01, 0.1, 0.0, 1.0, 0.0]
+            .iter()
+            .enumerate()
+        {
+            params.set_denorm_parameter(index as i32, *value);
+        }
+
+        VstCompressor {
+            compressor: effects::dynamics::Compressor::new(48000),
+            sidechain: false,
+            params: std::sync::Arc::new(params),
+        }
+    }
+}
+
+impl Plugin for VstCompressor {
+    fn get_info(&self) -> Info {
+        Info {
+            name: "dws_compressor".to_string(),
+            unique_id: 84781387, // Used by hosts to differentiate between plugins.
+            inputs: 4,
+            outputs: 2,
+            parameters: 8,
+
+            ..Default::default()
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.compressor = effects::dynamics::Compressor::new(rate as usize);
+        // Apply all parameters again to the new compressor.
+        for index in 0..8 {
+            self.params
+                .set_denorm_parameter(index, self.params.get_denorm_parameter(index));
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        self.params.clone()
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        for (index, value) in self.params.param_transfer.iterate(true) {
+            match index {
+                0 => self.compressor.set_threshold(value as f64),
+                1 => self.compressor.set_ratio(value as f64),
+                2 => self.compressor.set_knee(value as f64),
+                3 => self.compressor.set_attack(value as f64),
+                4 => self.compressor.set_release(value as f64),
+                5 => self.compressor.set_makeup(value as f64),
+                6 => self.compressor.set_stereo_link(value as f64),
+                7 => self.sidechain = value > 0.5,
+                _ => {}
+            }
+        }
+
+        let (inputs, mut outputs) = buffer.split();
+
+        // Hosts that do not connect the sidechain pair may leave it out.
+        let sidechain = if self.sidechain && inputs.len() >= 4 {
+            (2, 3)
+        } else {
+            (0, 1)
+        };
+
+        let left_in = inputs.get(0).iter();
+        let right_in = inputs.get(1).iter();
+        let left_sidechain = inputs.get(sidechain.0).iter();
+        let right_sidechain = inputs.get(sidechain.1).iter();
+
+        let left_out = outputs.get_mut(0).iter_mut();
+        let right_out = outputs.get_mut(1).iter_mut();
+
+        for (((li, ri), (ls, rs)), (lo, ro)) in left_in
+            .zip(right_in)
+            .zip(left_sidechain.zip(right_sidechain))
+            .zip(left_out.zip(right_out))
+        {
+            let o = self.compressor.tick_with_sidechain([*li, *ri], [*ls, *rs]);
+            *lo = o[0];
+            *ro = o[1];
+        }
+    }
+}
+
+/// Lowest and highest ring modulator oscillator frequencies, in Hz.
+const RING_MOD_MIN_FREQUENCY: f32 = 20.0;
+const RING_MOD_MAX_FREQUENCY: f32 = 5000.0;
+
+const RING_MOD_WAVEFORMS: [lfo::Waveform; 3] = [
+    lfo::Waveform::Sine,
+    lfo::Waveform::Square,
+    lfo::Waveform::Triangle,
+];
+
+struct RingModParams {
+    param_transfer: ParameterTransfer,
+}
+
+impl RingModParams {
+    fn normalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => {
+                (value / RING_MOD_MIN_FREQUENCY).ln()
+                    / (RING_MOD_MAX_FREQUENCY / RING_MOD_MIN_FREQUENCY).ln()
+            }
+            1 => value / (RING_MOD_WAVEFORMS.len() - 1) as f32,
+            2..=4 => value,
+            _ => 0.0,
+        }
+    }
+
+    fn denormalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => {
+                RING_MOD_MIN_FREQUENCY
+                    * (RING_MOD_MAX_FREQUENCY / RING_MOD_MIN_FREQUENCY).powf(value)
+            }
+            1 => (value * (RING_MOD_WAVEFORMS.len() - 1) as f32).round(),
+            2 => value,
+            3 | 4 => value.round(),
+            _ => 0.0,
+        }
+    }
+
+    fn get_denorm_parameter(&self, index: i32) -> f32 {
+        self.param_transfer.get_parameter(index as usize)
+    }
+
+    fn set_denorm_parameter(&self, index: i32, value: f32) {
+        self.param_transfer.set_parameter(index as usize, value);
+    }
+}
+
+impl PluginParameters for RingModParams {
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "frequency".to_string(),
+            1 => "waveform".to_string(),
+            2 => "mix".to_string(),
+            3 => "carrier".to_string(),
+            4 => "am".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            0 => "Hz".to_string(),
+            1..=4 => "".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        RingModParams::normalize_parameter(index, self.get_denorm_parameter(index))
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            1 => match self.get_denorm_parameter(index) as usize {
+                0 => "sine".to_string(),
+                1 => "square".to_string(),
+                _ => "triangle".to_string(),
+            },
+            3 if self.get_denorm_parameter(index) > 0.5 => "sidechain".to_string(),
+            3 => "oscillator".to_string(),
+            4 if self.get_denorm_parameter(index) > 0.5 => "on".to_string(),
+            4 => "off".to_string(),
+            _ => format!("{number:.3}", number = self.get_denorm_parameter(index)),
+        }
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        self.set_denorm_parameter(index, RingModParams::denormalize_parameter(index, value));
+    }
+}
+
+/// Ring modulator whose carrier is either its oscillator or a second input pair.
+pub struct VstRingMod {
+    ring_mod: effects::RingMod<Stereo<f32>>,
+    sidechain: bool,
+    params: Arc<RingModParams>,
+}
+
+impl Default for VstRingMod {
+    fn default() -> VstRingMod {
+        let params = RingModParams {
+            param_transfer: ParameterTransfer::new(5),
+        };
+        // Start the host side out at the same settings as the ring modulator.
+        for (index, value) in [440.0, 0.0, 1.0, 0.0, 0.0].iter().enumerate() {
+            params.set_denorm_parameter(index as i32, *value);
+        }
+
+        VstRingMod {
+            ring_mod: effects::RingMod::new(440.0, 48000),
+            sidechain: false,
+            params: std::sync::Arc::new(params),
+        }
+    }
+}
+
+impl Plugin for VstRingMod {
+    fn get_info(&self) -> Info {
+        Info {
+            name: "dws_ring_mod".to_string(),
+            unique_id: 84781388, // Used by hosts to differentiate between plugins.
+            inputs: 4,
+            outputs: 2,
+            parameters: 5,
+
+            ..Default::default()
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.ring_mod = effects::RingMod::new(440.0, rate as usize);
+        // Apply all parameters again to the new ring modulator.
+        for index in 0..5 {
+            self.params
+                .set_denorm_parameter(index, self.params.get_denorm_parameter(index));
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        self.params.clone()
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        for (index, value) in self.params.param_transfer.iterate(true) {
+            match index {
+                0 => self.ring_mod.set_frequency(value as f64),
+                1 => self
+                    .ring_mod
+                    .set_waveform(RING_MOD_WAVEFORMS[value as usize]),
+                2 => self.ring_mod.set_mix(value as f64),
+                3 => self.sidechain = value > 0.5,
+                4 => self.ring_mod.set_am(value > 0.5),
+                _ => {}
+            }
+        }
+
+        let (inputs, mut outputs) = buffer.split();
+
+        // Without a connected sidechain pair fall back to the oscillator.
+        let sidechain = self.sidechain && inputs.len() >= 4;
+        let carrier = if sidechain { (2, 3) } else { (0, 1) };
+
+        let left_in = inputs.get(0).iter();
+        let right_in = inputs.get(1).iter();
+        let left_carrier = inputs.get(carrier.0).iter();
+        let right_carrier = inputs.get(carrier.1).iter();
+
+        let left_out = outputs.get_mut(0).iter_mut();
+        let right_out = outputs.get_mut(1).iter_mut();
+
+        for (((li, ri), (lc, rc)), (lo, ro)) in left_in
+            .zip(right_in)
+            .zip(left_carrier.zip(right_carrier))
+            .zip(left_out.zip(right_out))
+        {
+            let o = if sidechain {
+                self.ring_mod.tick_with_carrier([*li, *ri], [*lc, *rc])
+            } else {
+                self.ring_mod.tick([*li, *ri])
+            };
+            *lo = o[0];
+            *ro = o[1];
+        }
+    }
+}
+
+/// Lowest and highest auto-wah centre frequencies, in Hz.
+const AUTO_WAH_MIN_FREQUENCY: f32 = 100.0;
+const AUTO_WAH_MAX_FREQUENCY: f32 = 8000.0;
+
+struct AutoWahParams {
+    param_transfer: ParameterTransfer,
+}
+
+impl AutoWahParams {
+    fn normalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => value / 40.0,
+            1 => value / 0.1,
+            2 => value,
+            3 | 4 => {
+                (value / AUTO_WAH_MIN_FREQUENCY).ln()
+                    / (AUTO_WAH_MAX_FREQUENCY / AUTO_WAH_MIN_FREQUENCY).ln()
+            }
+            5 => (value - 0.5) / 19.5,
+            6 => value,
+            7 => value / 10.0,
+            8 => value,
+            _ => 0.0,
+        }
+    }
+
+    fn denormalize_parameter(index: i32, value: f32) -> f32 {
+        match index {
+            0 => value * 40.0,
+            1 => value * 0.1,
+            2 => value,
+            3 | 4 => {
+                AUTO_WAH_MIN_FREQUENCY
+                    * (AUTO_WAH_MAX_FREQUENCY / AUTO_WAH_MIN_FREQUENCY).powf(value)
+            }
+            5 => value * 19.5 + 0.5,
+            6 => value.round(),
+            7 => value * 10.0,
+            8 => value,
+            _ => 0.0,
+        }
+    }
+
+    fn get_denorm_parameter(&self, index: i32) -> f32 {
+        self.param_transfer.get_parameter(index as usize)
+    }
+
+    fn set_denorm_parameter(&self, index: i32, value: f32) {
+        self.param_transfer.set_parameter(index as usize, value);
+    }
+}
+
+impl PluginParameters for AutoWahParams {
+    fn get_parameter_name(&self, index: i32) -> String {
+        match index {
+            0 => "sensitivity".to_string(),
+            1 => "attack".to_string(),
+            2 => "release".to_string(),
+            3 => "low frequency".to_string(),
+            4 => "high frequency".to_string(),
+            5 => "resonance".to_string(),
+            6 => "mode".to_string(),
+            7 => "lfo rate".to_string(),
+            8 => "mix".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter_label(&self, index: i32) -> String {
+        match index {
+            0 => "dB".to_string(),
+            1 | 2 => "s".to_string(),
+            3 | 4 | 7 => "Hz".to_string(),
+            5 | 6 | 8 => "".to_string(),
+            _ => "computer says no".to_string(),
+        }
+    }
+
+    fn get_parameter(&self, index: i32) -> f32 {
+        AutoWahParams::normalize_parameter(index, self.get_denorm_parameter(index))
+    }
+
+    fn get_parameter_text(&self, index: i32) -> String {
+        match index {
+            6 if self.get_denorm_parameter(index) > 0.5 => "lfo".to_string(),
+            6 => "envelope".to_string(),
+            _ => format!("{number:.3}", number = self.get_denorm_parameter(index)),
+        }
+    }
+
+    fn set_parameter(&self, index: i32, value: f32) {
+        self.set_denorm_parameter(index, AutoWahParams::denormalize_parameter(index, value));
+    }
+}
+
+/// Auto-wah swept by the input level or by an LFO.
+pub struct VstAutoWah {
+    auto_wah: effects::AutoWah<Stereo<f32>>,
+    // The range is set as a pair, so both ends are kept until either changes.
+    range: (f64, f64),
+    params: Arc<AutoWahParams>,
+}
+
+impl Default for VstAutoWah {
+    fn default() -> VstAutoWah {
+        let params = AutoWahParams {
+            param_transfer: ParameterTransfer::new(9),
+        };
+        // Start the host side out at the same settings as the auto-wah.
+        for (index, value) in [20.0, 0.005, 0.1, 300.0, 2500.0, 4.0, 0.0, 1.0, 1.0]
+            .iter()
+            .enumerate()
+        {
+            params.set_denorm_parameter(index as i32, *value);
+        }
+
+        VstAutoWah {
+            auto_wah: effects::AutoWah::new(48000),
+            range: (300.0, 2500.0),
+            params: std::sync::Arc::new(params),
+        }
+    }
+}
+
+impl Plugin for VstAutoWah {
+    fn get_info(&self) -> Info {
+        Info {
+            name: "dws_auto_wah".to_string(),
+            unique_id: 84781389, // Used by hosts to differentiate between plugins.
+            inputs: 2,
+            outputs: 2,
+            parameters: 9,
+
+            ..Default::default()
+        }
+    }
+
+    fn set_sample_rate(&mut self, rate: f32) {
+        self.auto_wah = effects::AutoWah::new(rate as usize);
+        // Apply all parameters again to the new auto-wah.
+        for index in 0..9 {
+            self.params
+                .set_denorm_parameter(index, self.params.get_denorm_parameter(index));
+        }
+    }
+
+    fn get_parameter_object(&mut self) -> Arc<dyn PluginParameters> {
+        self.params.clone()
+    }
+
+    fn process(&mut self, buffer: &mut AudioBuffer<f32>) {
+        for (index, value) in self.params.param_transfer.iterate(true) {
+            match index {
+                0 => self.auto_wah.set_sensitivity(value as f64),
+                1 => self.auto_wah.set_attack(value as f64),
+                2 => self.auto_wah.set_release(value as f64),
+                3 => {
+                    self.range.0 = value as f64;
+                    self.auto_wah.set_range(self.range.0, self.range.1);
+                }
+                4 => {
+                    self.range.1 = value as f64;
+                    self.auto_wah.set_range(self.range.0, self.range.1);
+                }
+                5 => self.auto_wah.set_resonance(value as f64),
+                6 => self.auto_wah.set_lfo(value > 0.5),
+                7 => self.auto_wah.set_lfo_rate(value as f64),
+                8 => self.auto_wah.set_mix(value as f64),
+                _ => {}
+            }
+        }
+
+        let (inputs, mut outputs) = buffer.split();
+
+        let left_in = inputs.get(0).iter();
+        let right_in = inputs.get(1).iter();
+
+        let left_out = outputs.get_mut(0).iter_mut();
+        let right_out = outputs.get_mut(1).iter_mut();
+
+        for ((li, ri), (lo, ro)) in left_in.zip(right_in).zip(left_out.zip(right_out)) {
+            let o = self.auto_wah.tick([*li, *ri]);
+            *lo = o[0];
+            *ro = o[1];
+        }
+    }
+}
+
+pub struct VstPluckedString {
     plucked_string: instruments::PluckedString<f32>,
     params: Arc<PluckedStringParams>,
 }
@@ -180,13 +1113,14 @@ impl Plugin for VstPluckedString {
 
         let (_, mut outputs) = buffer.split();
 
-        let output = outputs.get_mut(0).into_iter();
+        let output = outputs.get_mut(0).iter_mut();
 
         for o in output {
             *o = self.plucked_string.tick()[0];
         }
     }
 
+    #[allow(clippy::single_match, clippy::unnecessary_cast)]
     fn process_events(&mut self, events: &Events) {
         // Some events aren't MIDI events - so let's do a match
         // to make sure we only get MIDI, since that's all we care about.
@@ -278,5 +1212,3 @@ impl PluginParameters for PluckedStringParams {
         );
     }
 }
-
-plugin_main!(VstPluckedString);
@@ -0,0 +1,234 @@
+use crate::effects::EchoParameters;
+
+pub const SPEED_OF_SOUND: f64 = 343.0;
+
+/// A shoebox shaped room with one corner at the origin, for the image-source method.
+///
+/// Each wall absorbs a fraction of the energy hitting it. The walls are ordered
+/// x = 0, x = width, y = 0, y = depth, z = 0 (the floor), z = height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Room {
+    pub dimensions: [f64; 3],
+    pub absorption: [f64; 6],
+}
+
+/// The mirror image of a source, and how many times the sound reflects off each wall to get there.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageSource {
+    pub position: [f64; 3],
+    pub reflections: [u32; 6],
+}
+
+impl ImageSource {
+    pub fn order(&self) -> u32 {
+        self.reflections.iter().sum()
+    }
+}
+
+impl Room {
+    pub fn new(dimensions: [f64; 3], absorption: [f64; 6]) -> Self {
+        assert!(dimensions.iter().all(|d| *d > 0.0));
+        assert!(absorption.iter().all(|a| (0.0..=1.0).contains(a)));
+
+        Room {
+            dimensions,
+            absorption,
+        }
+    }
+
+    /// All image sources up to and including the given reflection order. Order 0 is the source.
+    pub fn image_sources(&self, source: [f64; 3], order: usize) -> Vec<ImageSource> {
+        let order = order as i64;
+
+        // Along one axis an image is (1 - 2q) * x + 2 * n * length. It has reflected |n - q| times
+        // off the wall at 0 and |n| times off the wall at length.
+        let axis_images = |axis: usize| {
+            let mut images = vec![];
+            for n in -order..=order {
+                for q in 0..2 {
+                    let position =
+                        (1 - 2 * q) as f64 * source[axis] + 2.0 * n as f64 * self.dimensions[axis];
+                    let reflections = ((n - q).unsigned_abs() as u32, n.unsigned_abs() as u32);
+                    if (reflections.0 + reflections.1) as i64 <= order {
+                        images.push((position, reflections));
+                    }
+                }
+            }
+            images
+        };
+
+        let (xs, ys, zs) = (axis_images(0), axis_images(1), axis_images(2));
+        let mut images = vec![];
+        for x in xs.iter() {
+            for y in ys.iter() {
+                for z in zs.iter() {
+                    let image = ImageSource {
+                        position: [x.0, y.0, z.0],
+                        reflections: [x.1 .0, x.1 .1, y.1 .0, y.1 .1, z.1 .0, z.1 .1],
+                    };
+                    if image.order() as i64 <= order {
+                        images.push(image);
+                    }
+                }
+            }
+        }
+
+        images.sort_by_key(|i| i.order());
+        images
+    }
+
+    /// Pressure gain of one bounce off a wall.
+    fn reflection_coefficient(&self, wall: usize) -> f64 {
+        (1.0 - self.absorption[wall]).sqrt()
+    }
+
+    /// Delay and gain of the direct sound and every reflection up to the given order.
+    ///
+    /// Like `EchoParameters::from_distances` these are relative to the direct sound, so the direct
+    /// sound has a delay of 0 and a gain of 1. Reflections off fully absorbing walls are left out.
+    pub fn reflections(
+        &self,
+        source: [f64; 3],
+        listener: [f64; 3],
+        order: usize,
+        sample_rate: usize,
+    ) -> Vec<EchoParameters> {
+        let direct = distance(source, listener);
+        let frame_t = 1.0 / sample_rate as f64;
+
+        self.image_sources(source, order)
+            .iter()
+            .filter_map(|image| {
+                let path = distance(image.position, listener);
+                let wall_gain: f64 = image
+                    .reflections
+                    .iter()
+                    .enumerate()
+                    .map(|(wall, count)| self.reflection_coefficient(wall).powi(*count as i32))
+                    .product();
+
+                if wall_gain == 0.0 {
+                    return None;
+                }
+
+                Some(EchoParameters {
+                    attenuation: (wall_gain * direct / path) as f32,
+                    length: ((path - direct) / (SPEED_OF_SOUND * frame_t)).round() as usize,
+                })
+            })
+            .collect()
+    }
+
+    /// Renders the reflections into an impulse response, relative to the direct sound.
+    pub fn impulse_response(
+        &self,
+        source: [f64; 3],
+        listener: [f64; 3],
+        order: usize,
+        sample_rate: usize,
+    ) -> Vec<f64> {
+        let reflections = self.reflections(source, listener, order, sample_rate);
+        let length = reflections.iter().map(|r| r.length).max().unwrap_or(0) + 1;
+
+        let mut response = vec![0.0; length];
+        for r in reflections.iter() {
+            response[r.length] += r.attenuation as f64;
+        }
+        response
+    }
+}
+
+pub fn distance(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>()
+        .sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::effects::MultiTapDelay;
+    use approx::assert_relative_eq;
+
+    #[test]
+    pub fn image_source_counts() {
+        // In three dimensions there are 4k^2 + 2 images of order k.
+        let room = Room::new([5.0, 4.0, 3.0], [0.0; 6]);
+        let images = room.image_sources([1.0, 2.0, 1.5], 3);
+
+        assert_eq!(images.len(), 1 + 6 + 18 + 38);
+        assert_eq!(images.iter().filter(|i| i.order() == 2).count(), 18);
+        assert_eq!(images[0].position, [1.0, 2.0, 1.5]);
+    }
+
+    #[test]
+    pub fn first_order_reflections() {
+        let room = Room::new([5.0, 4.0, 3.0], [0.19; 6]);
+        let source = [1.0, 1.0, 1.0];
+        let listener = [4.0, 3.0, 1.5];
+        let sample_rate = 48000;
+
+        // The source mirrored in each of the six walls.
+        let images = [
+            [-1.0, 1.0, 1.0],
+            [9.0, 1.0, 1.0],
+            [1.0, -1.0, 1.0],
+            [1.0, 7.0, 1.0],
+            [1.0, 1.0, -1.0],
+            [1.0, 1.0, 5.0],
+        ];
+        let direct = (9.0f64 + 4.0 + 0.25).sqrt();
+
+        let reflections = room.reflections(source, listener, 1, sample_rate);
+        assert_eq!(reflections.len(), 7);
+        assert_eq!(reflections[0].length, 0);
+        assert_relative_eq!(reflections[0].attenuation, 1.0);
+
+        for image in images.iter() {
+            let path = distance(*image, listener);
+            let length = ((path - direct) * sample_rate as f64 / SPEED_OF_SOUND).round() as usize;
+            // A wall absorbing 19% of the energy reflects 90% of the pressure.
+            let attenuation = (0.9 * direct / path) as f32;
+
+            assert!(reflections.iter().any(|r| r.length == length
+                && approx::relative_eq!(r.attenuation, attenuation, epsilon = 1e-6)));
+        }
+    }
+
+    #[test]
+    pub fn floor_reflection_matches_echo() {
+        // Only a floor, the other walls absorb everything.
+        let room = Room::new([1000.0; 3], [1.0, 1.0, 1.0, 1.0, 0.0, 1.0]);
+        let (d, h) = (20.0, 1.7);
+        let source = [500.0 - d / 2.0, 500.0, h];
+        let listener = [500.0 + d / 2.0, 500.0, h];
+
+        let reflections = room.reflections(source, listener, 1, 48000);
+        let expected = EchoParameters::from_distances(d as f32, h as f32, 48000);
+
+        assert_eq!(reflections.len(), 2);
+        assert_eq!(reflections[1].length, expected.length);
+        assert_relative_eq!(
+            reflections[1].attenuation,
+            expected.attenuation,
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    pub fn multi_tap_matches_impulse_response() {
+        let room = Room::new([6.0, 5.0, 2.5], [0.3, 0.3, 0.1, 0.5, 0.05, 0.7]);
+        let source = [2.0, 1.0, 1.2];
+        let listener = [3.5, 4.0, 1.6];
+
+        let response = room.impulse_response(source, listener, 3, 16000);
+        let mut delay = MultiTapDelay::<f64>::new(room.reflections(source, listener, 3, 16000));
+
+        for (n, expected) in response.iter().enumerate() {
+            let out = delay.tick(if n == 0 { 1.0 } else { 0.0 });
+            assert_relative_eq!(out, *expected, epsilon = 1e-6);
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::io::{self, Read};
+use std::path::Path;
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Audio read from a WAV file, one vector of samples in [-1, 1] per channel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Wav {
+    pub sample_rate: usize,
+    pub channels: Vec<Vec<f64>>,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+impl Wav {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Wav> {
+        Wav::from_reader(std::fs::File::open(path)?)
+    }
+
+    /// Reads 16, 24 and 32 bit integer PCM and 32 and 64 bit float WAV data with any number of
+    /// channels.
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Wav> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err(invalid("not a RIFF WAVE file"));
+        }
+
+        let mut format = None;
+        let mut data = None;
+        let mut at = 12;
+        while at + 8 <= bytes.len() {
+            let id = &bytes[at..at + 4];
+            let size = u32_at(&bytes, at + 4) as usize;
+            let body = at + 8;
+            if body + size > bytes.len() {
+                return Err(invalid("truncated chunk"));
+            }
+
+            match id {
+                b"fmt " => {
+                    if size < 16 {
+                        return Err(invalid("fmt chunk too short"));
+                    }
+                    let mut tag = u16_at(&bytes, body);
+                    if tag == FORMAT_EXTENSIBLE {
+                        if size < 40 {
+                            return Err(invalid("extensible fmt chunk too short"));
+                        }
+                        // The sub format GUID starts with the actual format tag.
+                        tag = u16_at(&bytes, body + 24);
+                    }
+                    format = Some((
+                        tag,
+                        u16_at(&bytes, body + 2) as usize,
+                        u32_at(&bytes, body + 4) as usize,
+                        u16_at(&bytes, body + 14) as usize,
+                    ));
+                }
+                b"data" => data = Some(&bytes[body..body + size]),
+                _ => {}
+            }
+
+            // Chunks are padded to an even length.
+            at = body + size + size % 2;
+        }
+
+        let (tag, channel_count, sample_rate, bits) =
+            format.ok_or_else(|| invalid("missing fmt chunk"))?;
+        let data = data.ok_or_else(|| invalid("missing data chunk"))?;
+        if channel_count == 0 {
+            return Err(invalid("no channels"));
+        }
+
+        let decode: fn(&[u8]) -> f64 = match (tag, bits) {
+            (FORMAT_PCM, 16) => |b| i16::from_le_bytes([b[0], b[1]]) as f64 / 32768.0,
+            (FORMAT_PCM, 24) => {
+                |b| (i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8) as f64 / 8388608.0
+            }
+            (FORMAT_PCM, 32) => {
+                |b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64 / 2147483648.0
+            }
+            (FORMAT_FLOAT, 32) => |b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f64,
+            (FORMAT_FLOAT, 64) => {
+                |b| f64::from_le_bytes([b[0], b[1], b[2], b[3], b[4], b[5], b[6], b[7]])
+            }
+            _ => return Err(invalid("unsupported sample format")),
+        };
+
+        let sample_bytes = bits / 8;
+        let mut channels = vec![vec![]; channel_count];
+        for frame in data.chunks_exact(sample_bytes * channel_count) {
+            for (channel, sample) in channels.iter_mut().zip(frame.chunks_exact(sample_bytes)) {
+                channel.push(decode(sample));
+            }
+        }
+
+        Ok(Wav {
+            sample_rate,
+            channels,
+        })
+    }
+
+    /// Number of frames.
+    pub fn len(&self) -> usize {
+        self.channels[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a WAV file around already encoded interleaved sample data.
+    fn wav_bytes(tag: u16, channels: u16, bits: u16, data: &[u8], extensible: bool) -> Vec<u8> {
+        let mut fmt = vec![];
+        fmt.extend_from_slice(&(if extensible { FORMAT_EXTENSIBLE } else { tag }).to_le_bytes());
+        fmt.extend_from_slice(&channels.to_le_bytes());
+        fmt.extend_from_slice(&44100u32.to_le_bytes());
+        let block_align = channels * bits / 8;
+        fmt.extend_from_slice(&(44100 * block_align as u32).to_le_bytes());
+        fmt.extend_from_slice(&block_align.to_le_bytes());
+        fmt.extend_from_slice(&bits.to_le_bytes());
+        if extensible {
+            fmt.extend_from_slice(&22u16.to_le_bytes());
+            fmt.extend_from_slice(&bits.to_le_bytes());
+            fmt.extend_from_slice(&0u32.to_le_bytes());
+            fmt.extend_from_slice(&tag.to_le_bytes());
+            fmt.extend_from_slice(&[0; 14]);
+        }
+
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend_from_slice(b"fmt ");
+        bytes.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&fmt);
+        // An unknown odd sized chunk that has to be skipped.
+        bytes.extend_from_slice(b"junk\x03\0\0\0abc\0");
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+
+    #[test]
+    pub fn pcm_formats() {
+        let data16: Vec<u8> = [16384i16, -32768, 0, 32767]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        let wav = Wav::from_reader(&wav_bytes(FORMAT_PCM, 2, 16, &data16, false)[..]).unwrap();
+        assert_eq!(wav.sample_rate, 44100);
+        assert_eq!(
+            wav.channels,
+            vec![vec![0.5, 0.0], vec![-1.0, 32767.0 / 32768.0]]
+        );
+
+        // -0.5 and 0.25 in 24 bits
+        let data24 = [0x00, 0x00, 0xC0, 0x00, 0x00, 0x20];
+        let wav = Wav::from_reader(&wav_bytes(FORMAT_PCM, 1, 24, &data24, true)[..]).unwrap();
+        assert_eq!(wav.channels, vec![vec![-0.5, 0.25]]);
+
+        let data32: Vec<u8> = [-1073741824i32, 536870912]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        let wav = Wav::from_reader(&wav_bytes(FORMAT_PCM, 1, 32, &data32, false)[..]).unwrap();
+        assert_eq!(wav.channels, vec![vec![-0.5, 0.25]]);
+    }
+
+    #[test]
+    pub fn float_formats() {
+        let data32: Vec<u8> = [0.125f32, -0.75, 1.0, 0.0]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        let wav = Wav::from_reader(&wav_bytes(FORMAT_FLOAT, 4, 32, &data32, false)[..]).unwrap();
+        assert_eq!(
+            wav.channels,
+            vec![vec![0.125], vec![-0.75], vec![1.0], vec![0.0]]
+        );
+
+        let data64: Vec<u8> = [0.1f64, -0.2]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        let wav = Wav::from_reader(&wav_bytes(FORMAT_FLOAT, 1, 64, &data64, true)[..]).unwrap();
+        assert_eq!(wav.channels, vec![vec![0.1, -0.2]]);
+        assert_eq!(wav.len(), 2);
+    }
+
+    #[test]
+    pub fn rejects_bad_files() {
+        assert!(Wav::from_reader(&b"RIFX\0\0\0\0WAVE"[..]).is_err());
+        assert!(Wav::from_reader(&wav_bytes(FORMAT_PCM, 1, 8, &[1, 2], false)[..]).is_err());
+    }
+
+    #[test]
+    pub fn open_file() {
+        let path = std::env::temp_dir().join("dws_wav_open_file_test.wav");
+        let data: Vec<u8> = [0.5f32, -0.5]
+            .iter()
+            .flat_map(|s| s.to_le_bytes().to_vec())
+            .collect();
+        std::fs::write(&path, wav_bytes(FORMAT_FLOAT, 1, 32, &data, false)).unwrap();
+
+        let wav = Wav::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(wav.channels, vec![vec![0.5, -0.5]]);
+    }
+}